@@ -1,16 +1,37 @@
 use std::fs;
+use std::io;
 
+use chrono::DateTime;
+use chrono::FixedOffset;
+use chrono::Local;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
 use clap::App as Cli;
 use clap::Arg;
 use clap::ArgMatches;
 use clap::SubCommand;
 use log;
+use pager::Pager;
 
 use env_logger;
 use lib::config;
+use lib::config::DbEngine;
+use lib::config::DumpFormat;
 use lib::config::JabConfig;
 use lib::config::ProjectConfig;
+use lib::db::mysql::client as mysql;
 use lib::db::postgresql::client as pg;
+use lib::db::postgresql::data::display_value;
+use lib::db::postgresql::data::Row;
+use lib::db::postgresql::data::TableDataDiff;
+use lib::db::postgresql::data::TableSummaryDiff;
+use lib::db::postgresql::schema::SchemaChange;
+use lib::db::postgresql::schema::SchemaObjectDiff;
+use lib::db_state::DbState;
+use lib::db_state::DbStateAction;
+use lib::git::Commit;
+use lib::git::CommitFilter;
 use lib::project;
 use lib::project::Project;
 use lib::project_manager::CreateProjectInput;
@@ -60,8 +81,39 @@ fn project_cmd<'a, 'b>() -> Cli<'a, 'b> {
           Arg::with_name("database-uri")
             .long("database-uri")
             .takes_value(true)
-            .required(true)
+            .required_unless("service")
             .help(r#"Database uri, for example: --database-uri="user:secret@localhost/mydb""#),
+        )
+        .arg(
+          Arg::with_name("engine")
+            .long("engine")
+            .takes_value(true)
+            .possible_values(&["postgresql", "mysql"])
+            .default_value("postgresql")
+            .help("Database server, mysql covers MariaDB too"),
+        )
+        .arg(
+          Arg::with_name("service")
+            .long("service")
+            .takes_value(true)
+            .help("Pg service to connect with, defined in ~/.pg_service.conf or pg_service.conf"),
+        )
+        .arg(
+          Arg::with_name("password-env")
+            .long("password-env")
+            .takes_value(true)
+            .help("Environment variable to read the db password from"),
+        )
+        .arg(
+          Arg::with_name("dump-format")
+            .long("dump-format")
+            .takes_value(true)
+            .possible_values(&["tables", "custom", "plain", "directory"])
+            .default_value("tables")
+            .help(
+              "How dumps are stored, tables keeps a schema file and one sql file per table \
+               so git diffs and deltas work per table. Mysql projects are always plain",
+            ),
         ),
     )
     .subcommand(SubCommand::with_name("list").about("List projects"))
@@ -81,13 +133,144 @@ fn project_cmd<'a, 'b>() -> Cli<'a, 'b> {
     .subcommand(
       SubCommand::with_name("log")
         .arg(project_name_arg.clone())
-        .about("Show list of changes log"),
+        .about("Show list of changes log")
+        .arg(
+          Arg::with_name("limit")
+            .long("limit")
+            .short("n")
+            .takes_value(true)
+            .validator(|limit| {
+              limit
+                .parse::<usize>()
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+            })
+            .help("Maximum number of commits to show"),
+        )
+        .arg(
+          Arg::with_name("since")
+            .long("since")
+            .takes_value(true)
+            .help(
+              "Show commits at or after the given date, e.g. 2020-01-31 or 2020-01-31 13:00:00",
+            ),
+        )
+        .arg(
+          Arg::with_name("until")
+            .long("until")
+            .takes_value(true)
+            .help("Show commits at or before the given date"),
+        )
+        .arg(
+          Arg::with_name("grep")
+            .long("grep")
+            .takes_value(true)
+            .help("Show commits whose message contains the given text"),
+        )
+        .arg(
+          Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .default_value("oneline")
+            .help(
+              "oneline, medium or a template with placeholders \
+               %H (hash), %h (short hash), %P (parents), %an (author), \
+               %ad (date), %s (summary), %z (dump size), %D (marks the db state)",
+            ),
+        ),
     )
     .subcommand(
       SubCommand::with_name("show")
-        .about("Show dump for a specific commit")
+        .about("Show dump for a specific commit as sql")
+        .arg(project_name_arg.clone())
+        .arg(
+          Arg::with_name("commit-hash")
+            .takes_value(true)
+            .help("Revision (hash, tag, branch, HEAD~1, ...), defaults to the latest commit"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("diff")
+        .about("Show schema changes between two commits")
+        .arg(project_name_arg.clone())
+        .arg(
+          Arg::with_name("data")
+            .long("data")
+            .help("Compare table rows instead of schema"),
+        )
+        .arg(
+          Arg::with_name("rows")
+            .long("rows")
+            .requires("data")
+            .help("Print the differing rows keyed by primary key"),
+        )
+        .arg(
+          Arg::with_name("from")
+            .takes_value(true)
+            .required(true)
+            .help("Revision (hash, tag, branch, HEAD~1, ...) to compare from"),
+        )
+        .arg(
+          Arg::with_name("to")
+            .takes_value(true)
+            .help("Revision to compare to, defaults to the latest commit"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("branch")
+        .about("List, create or delete branches")
+        .arg(project_name_arg.clone())
+        .arg(
+          Arg::with_name("name")
+            .takes_value(true)
+            .help("Branch name to create from the current commit, lists branches when omitted"),
+        )
+        .arg(
+          Arg::with_name("delete")
+            .long("delete")
+            .short("d")
+            .requires("name")
+            .help("Delete the branch instead"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("checkout")
+        .about("Switch to a branch and restore db to its latest commit")
+        .arg(project_name_arg.clone())
+        .arg(
+          Arg::with_name("branch")
+            .takes_value(true)
+            .required(true)
+            .help("Branch name"),
+        )
+        .arg(
+          Arg::with_name("no-restore")
+            .long("no-restore")
+            .help("Only switch the branch, leave the db as it is"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("tag")
+        .about("List, create or delete tags")
         .arg(project_name_arg.clone())
-        .arg(Arg::with_name("commit-hash").takes_value(true)),
+        .arg(
+          Arg::with_name("name")
+            .takes_value(true)
+            .help("Tag name to create, lists tags when omitted"),
+        )
+        .arg(
+          Arg::with_name("commit-hash")
+            .takes_value(true)
+            .conflicts_with("delete")
+            .help("Revision to tag, defaults to the latest commit"),
+        )
+        .arg(
+          Arg::with_name("delete")
+            .long("delete")
+            .short("d")
+            .requires("name")
+            .help("Delete the tag instead"),
+        ),
     )
     .subcommand(
       SubCommand::with_name("restore")
@@ -96,8 +279,82 @@ fn project_cmd<'a, 'b>() -> Cli<'a, 'b> {
         .arg(
           Arg::with_name("commit-hash")
             .required(false)
-            .takes_value(true),
+            .takes_value(true)
+            .help("Revision (hash, tag, branch, HEAD~1, ...), defaults to the latest commit"),
+        )
+        .arg(Arg::with_name("no-verify").long("no-verify").help(
+          "Skip dumping the restored db again to compare it against the commit, \
+               saves time on big dbs",
+        ))
+        .arg(
+          Arg::with_name("terminate-connections")
+            .long("terminate-connections")
+            .conflicts_with("no-terminate-connections")
+            .help(
+              "Terminate the sessions connected to the db before dropping it, \
+               the default for dbs on this machine",
+            ),
+        )
+        .arg(
+          Arg::with_name("no-terminate-connections")
+            .long("no-terminate-connections")
+            .help("Fail the restore when sessions are connected to the db"),
+        )
+        .arg(
+          Arg::with_name("no-snapshot")
+            .long("no-snapshot")
+            .help("Skip saving the current db state for undo-restore before restoring"),
+        )
+        .arg(Arg::with_name("swap").long("swap").help(
+          "Restore into a scratch db and swap it with the db once it succeeds, \
+               the db is untouched when the restore fails",
+        ))
+        .arg(
+          Arg::with_name("table")
+            .long("table")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help(
+              "Only replace the rows of this table, `table` or `schema.table`, \
+               other tables are left alone",
+            ),
+        )
+        .arg(
+          Arg::with_name("schema")
+            .long("schema")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only replace the rows of the tables in this schema"),
+        )
+        .arg(
+          Arg::with_name("target-uri")
+            .long("target-uri")
+            .takes_value(true)
+            .conflicts_with("target-db")
+            .help("Restore into this db instead of the project db, it is created if missing"),
+        )
+        .arg(
+          Arg::with_name("target-db")
+            .long("target-db")
+            .takes_value(true)
+            .help("Restore into this db next to the project db, e.g. --target-db mydb_old"),
         ),
+    )
+    .subcommand(
+      SubCommand::with_name("status")
+        .about("Show how the db differs from the latest commit")
+        .arg(project_name_arg.clone())
+        .arg(Arg::with_name("rows").long("rows").help(
+          "Show the changed rows, not only row counts per table, \
+               the rows of the db and the commit are held in memory",
+        )),
+    )
+    .subcommand(
+      SubCommand::with_name("undo-restore")
+        .about("Restore the db state saved right before the latest restore")
+        .arg(project_name_arg.clone()),
     );
 }
 
@@ -111,12 +368,24 @@ fn handle_project_cli(cli: &ArgMatches) -> ResultDynError<()> {
     log::debug!("Creating project...");
 
     let project_name = create_cli.value_of("name").unwrap();
-    let db_uri = create_cli.value_of("database-uri").unwrap();
+    let db_uri = create_cli.value_of("database-uri").unwrap_or("");
+    let engine = create_cli.value_of("engine").unwrap().parse()?;
+    let dump_format = match engine {
+      // mysqldump only writes sql scripts
+      DbEngine::Mysql if create_cli.occurrences_of("dump-format") == 0 => DumpFormat::Plain,
+      _ => create_cli.value_of("dump-format").unwrap().parse()?,
+    };
 
     let project = project_manager.create_project(&CreateProjectInput {
       project_dir: config::get_jab_dir().as_ref(),
       project_name,
+      engine,
       db_uri,
+      service: create_cli.value_of("service"),
+      password_env: create_cli.value_of("password-env"),
+      dump_format,
+      terminate_connections: None,
+      dump_options: &Default::default(),
     })?;
 
     println!("Done creating {}", project.name());
@@ -133,11 +402,12 @@ fn handle_project_cli(cli: &ArgMatches) -> ResultDynError<()> {
     let project = project_manager.open_project_from_args(commit_cli)?;
 
     let message = commit_cli.value_of("message").unwrap();
-    let dump_output = pg::dump(pg::DumpInput {
-      db_uri: project.db_uri(),
-    })?;
+    dump_project_db(&project)?;
 
-    project.commit_dump(message, dump_output)?;
+    match project.commit_dump(message)? {
+      Some(commit_hash) => println!("Committed {}", commit_hash),
+      None => println!("Nothing to commit, db is unchanged since the latest commit"),
+    }
   } else if let Some(log_cli) = cli.subcommand_matches("log") {
     let project = project_manager.open_project_from_args(log_cli)?;
 
@@ -150,49 +420,692 @@ fn handle_project_cli(cli: &ArgMatches) -> ResultDynError<()> {
       return Ok(());
     } else {
       let commit_iterator = commit_iterator_result.unwrap();
+      let filter = CommitFilter {
+        since: log_cli.value_of("since").map(parse_date).transpose()?,
+        until: log_cli.value_of("until").map(parse_date).transpose()?,
+        message_pattern: log_cli.value_of("grep").map(String::from),
+      };
+      let limit = log_cli
+        .value_of("limit")
+        .map_or(usize::MAX, |limit| limit.parse().unwrap());
+      let format = log_cli.value_of("format").unwrap();
+      let db_state = project.db_state()?;
+      let mut commit_count = 0;
+
+      Pager::with_default_pager("less").setup();
 
       for commit in commit_iterator {
+        if commit_count >= limit {
+          break;
+        }
+
         let commit = commit?;
-        println!("* {} {}", commit.hash, commit.message);
+
+        if !filter.matches(&commit) {
+          continue;
+        }
+
+        let commit_db_state = db_state
+          .as_ref()
+          .filter(|db_state| db_state.commit_hash == commit.hash);
+
+        println!("{}", format_commit(&commit, format, commit_db_state)?);
+        commit_count += 1;
       }
     }
   } else if let Some(show_cli) = cli.subcommand_matches("show") {
-    println!("Operation not supported yet..");
-  // let project = project_manager.open_project_from_args(show_cli)?;
-  // let commit_hash = show_cli.value_of("commit-hash").unwrap();
-  // log::debug!("Reading commit {}...", commit_hash);
-  //
-  // TODO: Show commit details!
-  // let dump = repo.get_dump_at_commit(String::from(commit_hash))?;
+    let project = project_manager.open_project_from_args(show_cli)?;
+
+    let commit_hash = show_cli.value_of("commit-hash").unwrap_or("HEAD");
+
+    log::debug!("Reading commit {}...", commit_hash);
+
+    let dump = project.export_dump_at_commit(commit_hash)?;
+
+    Pager::with_default_pager("less").setup();
+
+    let written = pg::write_sql(
+      pg::ToSqlInput {
+        dump_path: &dump.path,
+        format: dump.format,
+        content: pg::SqlContent::Full,
+      },
+      &mut io::stdout(),
+    );
+
+    match written {
+      // The reader of stdout quit early, e.g. `head`
+      Err(err) if is_broken_pipe(&err) => {}
+      written => written?,
+    }
+  } else if let Some(diff_cli) = cli.subcommand_matches("diff") {
+    let project = project_manager.open_project_from_args(diff_cli)?;
+
+    let from = diff_cli.value_of("from").unwrap();
+    let to = diff_cli
+      .value_of("to")
+      .map(String::from)
+      .map(Ok)
+      .unwrap_or_else(|| project.last_commit_hash())?;
+
+    if diff_cli.is_present("data") {
+      log::debug!("Diffing data {}..{}", from, to);
+
+      print_data_diffs(project.diff_data(from, &to)?, diff_cli.is_present("rows"));
+    } else {
+      log::debug!("Diffing schema {}..{}", from, to);
+
+      print_schema_diffs(project.diff_schema(from, &to)?);
+    }
+  } else if let Some(branch_cli) = cli.subcommand_matches("branch") {
+    let project = project_manager.open_project_from_args(branch_cli)?;
+
+    match branch_cli.value_of("name") {
+      Some(name) if branch_cli.is_present("delete") => {
+        project.delete_branch(name)?;
+
+        println!("Deleted branch {}", name);
+      }
+      Some(name) => {
+        project.create_branch(name)?;
+
+        println!("Created branch {}", name);
+      }
+      None => {
+        for branch in project.branches()? {
+          let marker = if branch.is_head { "*" } else { " " };
+
+          println!("{} {}", marker, branch.name);
+        }
+      }
+    }
+  } else if let Some(checkout_cli) = cli.subcommand_matches("checkout") {
+    let project = project_manager.open_project_from_args(checkout_cli)?;
+
+    let branch = checkout_cli.value_of("branch").unwrap();
+
+    project.checkout_branch(branch)?;
+
+    println!("Switched to branch {}", branch);
+
+    if !checkout_cli.is_present("no-restore") {
+      log::debug!("Restoring to the latest commit of {}", branch);
+
+      restore_commit(
+        &project,
+        &RestoreCommitInput {
+          db_uri: project.db_uri(),
+          commit_hash: "HEAD",
+          verify: true,
+          terminate_connections: project.terminate_connections()?,
+          swap: false,
+          snapshot: true,
+        },
+      )?;
+    }
+  } else if let Some(tag_cli) = cli.subcommand_matches("tag") {
+    let project = project_manager.open_project_from_args(tag_cli)?;
+
+    match tag_cli.value_of("name") {
+      Some(name) if tag_cli.is_present("delete") => {
+        project.delete_tag(name)?;
+
+        println!("Deleted tag {}", name);
+      }
+      Some(name) => {
+        let commit_hash = tag_cli
+          .value_of("commit-hash")
+          .map(String::from)
+          .map(Ok)
+          .unwrap_or_else(|| project.last_commit_hash())?;
+
+        let tagged_commit_hash = project.create_tag(name, &commit_hash)?;
+
+        println!("Tagged {} as {}", tagged_commit_hash, name);
+      }
+      None => {
+        for tag in project.tags()? {
+          println!("* {} {}", tag.commit_hash, tag.name);
+        }
+      }
+    }
   } else if let Some(restore_cli) = cli.subcommand_matches("restore") {
     let project = project_manager.open_project_from_args(restore_cli)?;
 
-    let commit_hash = restore_cli.value_of("commit-hash");
-
-    // TODO: This is impractical because it will unnecessarily increase the memory usage.
-    // but let's stick with this to target the functional feature first.
-    let dump = commit_hash
-      .map(|commit_hash| {
-        log::debug!("Reading commit {}...", commit_hash);
-        return project.get_dump_at_commit(commit_hash);
-      })
-      .or_else(|| {
-        log::debug!("Restoring to last commit");
-        return Some(project.get_latest_dump());
-      })
-      .unwrap()?;
-
-    let result = pg::restore(pg::RestoreInput {
+    let commit_hash = restore_cli.value_of("commit-hash").unwrap_or("HEAD");
+
+    log::debug!("Restoring commit {}...", commit_hash);
+
+    let postgresql_only_args = [
+      ("target-uri", "Restoring into another db"),
+      ("target-db", "Restoring into another db"),
+      ("table", "Partial restore"),
+      ("schema", "Partial restore"),
+      ("swap", "Swap restore"),
+      ("terminate-connections", "Terminating connections"),
+    ];
+
+    for (arg, feature) in postgresql_only_args.iter() {
+      if restore_cli.is_present(arg) {
+        project.require_postgresql(feature)?;
+      }
+    }
+
+    let target_db_uri = match (
+      restore_cli.value_of("target-uri"),
+      restore_cli.value_of("target-db"),
+    ) {
+      (Some(target_uri), _) => Some(String::from(target_uri)),
+      (None, Some(target_db)) => Some(pg::db_uri_with_db_name(project.db_uri(), target_db)?),
+      (None, None) => None,
+    };
+    let db_uri = target_db_uri.as_deref().unwrap_or(project.db_uri());
+
+    let terminate_connections = if restore_cli.is_present("terminate-connections") {
+      true
+    } else if restore_cli.is_present("no-terminate-connections") {
+      false
+    } else if target_db_uri.is_some() {
+      pg::is_local_db(db_uri)?
+    } else {
+      project.terminate_connections()?
+    };
+
+    let tables: Vec<String> = restore_cli
+      .values_of("table")
+      .map_or(vec![], |values| values.map(String::from).collect());
+    let schemas: Vec<String> = restore_cli
+      .values_of("schema")
+      .map_or(vec![], |values| values.map(String::from).collect());
+
+    // The snapshot is undone into the project db
+    let snapshot = target_db_uri.is_none() && !restore_cli.is_present("no-snapshot");
+
+    if !tables.is_empty() || !schemas.is_empty() {
+      let dump = project.export_dump_at_commit(commit_hash)?;
+      let restored_tables = pg::restore_tables(pg::RestoreTablesInput {
+        db_uri,
+        dump_path: &dump.path,
+        format: dump.format,
+        tables: &tables,
+        schemas: &schemas,
+        before_changing_db: &mut || save_pre_restore_snapshot_if(&project, snapshot),
+      })?;
+
+      println!("Restored {}", restored_tables.join(", "));
+
+      return Ok(());
+    }
+
+    restore_commit(
+      &project,
+      &RestoreCommitInput {
+        db_uri,
+        commit_hash,
+        verify: !restore_cli.is_present("no-verify"),
+        terminate_connections,
+        swap: restore_cli.is_present("swap"),
+        snapshot,
+      },
+    )?;
+  } else if let Some(status_cli) = cli.subcommand_matches("status") {
+    let project = project_manager.open_project_from_args(status_cli)?;
+
+    log::debug!("Comparing db against the latest commit");
+
+    let status = project.status(status_cli.is_present("rows"))?;
+
+    if let Some(branch) = project.branches()?.iter().find(|branch| branch.is_head) {
+      println!("On branch {}", branch.name);
+    }
+
+    let last_commit_hash = match &status.last_commit_hash {
+      Some(last_commit_hash) => last_commit_hash,
+      None => {
+        println!("No commits yet");
+        return Ok(());
+      }
+    };
+
+    match &status.matching_commit_hash {
+      Some(commit_hash) if commit_hash == last_commit_hash => {
+        println!("Db matches the latest commit {}", commit_hash)
+      }
+      Some(commit_hash) => println!("Db matches commit {}", commit_hash),
+      None => println!("Db does not match any commit of the branch"),
+    }
+
+    if let Some(db_state) = project.db_state()? {
+      println!(
+        "Db was {} {} {} at {}",
+        db_state.action.as_str(),
+        match db_state.action {
+          DbStateAction::Commit => "as",
+          DbStateAction::Restore => "to",
+        },
+        db_state.commit_hash,
+        db_state.time.to_rfc2822()
+      );
+    }
+
+    if status.is_clean() {
+      println!("Nothing to commit, db is clean");
+    } else {
+      println!("Changes since the latest commit {}:", last_commit_hash);
+      print_schema_diffs(status.schema_diffs);
+      match status.data_diffs {
+        Some(data_diffs) => print_data_diffs(data_diffs, true),
+        None => print_table_diffs(status.table_diffs),
+      }
+    }
+  } else if let Some(undo_restore_cli) = cli.subcommand_matches("undo-restore") {
+    let project = project_manager.open_project_from_args(undo_restore_cli)?;
+
+    match project.pre_restore_snapshot_hash()? {
+      Some(commit_hash) => {
+        log::debug!("Restoring pre restore snapshot {}...", commit_hash);
+
+        // Keeps the snapshot so undoing again gives the same state
+        restore_commit(
+          &project,
+          &RestoreCommitInput {
+            db_uri: project.db_uri(),
+            commit_hash: &commit_hash,
+            verify: true,
+            terminate_connections: project.terminate_connections()?,
+            swap: false,
+            snapshot: false,
+          },
+        )?;
+
+        println!("Restored the db state saved before the latest restore");
+      }
+      None => println!("Nothing to undo, no restore was done yet"),
+    }
+  }
+
+  return Ok(());
+}
+
+struct RestoreCommitInput<'a> {
+  /// The project db or another db to restore the commit into.
+  db_uri: &'a str,
+  commit_hash: &'a str,
+  /// Compare the restored db against the commit content when
+  /// the installed dump tool can reproduce it.
+  verify: bool,
+  /// Terminate the sessions connected to the db so it can be dropped.
+  terminate_connections: bool,
+  /// Restore into a scratch db and swap it with the db once it succeeds.
+  swap: bool,
+  /// Save the current db state for `undo-restore` right before the
+  /// restore changes the db.
+  snapshot: bool,
+}
+
+/// Restores the db to the commit, the dump is piped from the repo
+/// into the restore tool without being held in memory.
+fn restore_commit(project: &Project, input: &RestoreCommitInput) -> ResultDynError<()> {
+  let commit_hash = input.commit_hash;
+
+  if project.engine() == DbEngine::Postgresql {
+    warn_restore_compatibility(project, commit_hash)?;
+  }
+
+  let result = match project.engine() {
+    DbEngine::Postgresql => restore_postgresql_commit(project, input)?,
+    DbEngine::Mysql => mysql::restore(mysql::RestoreInput {
+      db_uri: input.db_uri,
+      write_dump: &mut |writer| project.write_dump_at_commit(commit_hash, writer),
+      verify_checksum: restore_verification_checksum(project, input)?.as_deref(),
+      dump_options: &project.get_dump_options_at_commit(commit_hash)?,
+      before_changing_db: &mut || save_pre_restore_snapshot_if(project, input.snapshot),
+    })?,
+  };
+
+  log::debug!("Result {}", result);
+
+  project.record_db_state(input.db_uri, commit_hash, DbStateAction::Restore)?;
+
+  return Ok(());
+}
+
+/// Terminates the connected sessions when asked to, then restores
+/// with pg_restore or psql and verifies the restored db.
+fn restore_postgresql_commit(
+  project: &Project,
+  input: &RestoreCommitInput,
+) -> ResultDynError<String> {
+  let commit_hash = input.commit_hash;
+
+  if input.terminate_connections {
+    let sessions = pg::active_sessions(input.db_uri)?;
+
+    if !sessions.is_empty() {
+      eprintln!("Terminating {} connected session(s):", sessions.len());
+
+      for session in sessions {
+        eprintln!("  {}", session);
+      }
+    }
+  }
+
+  let verify_checksum = restore_verification_checksum(project, input)?;
+  let dump_options = project.get_dump_options_at_commit(commit_hash)?;
+  let format = project.get_dump_format_at_commit(commit_hash)?;
+  let result = match format {
+    // pg_restore reads directory format dumps by file name
+    DumpFormat::Directory => {
+      let dump = project.export_dump_at_commit(commit_hash)?;
+
+      pg::restore(pg::RestoreInput {
+        db_uri: input.db_uri,
+        format,
+        dump: pg::DumpSource::Path(&dump.path),
+        verify_checksum: verify_checksum.as_deref(),
+        terminate_connections: input.terminate_connections,
+        swap: input.swap,
+        dump_options: &dump_options,
+        before_changing_db: &mut || save_pre_restore_snapshot_if(project, input.snapshot),
+      })?
+    }
+    _ => pg::restore(pg::RestoreInput {
+      db_uri: input.db_uri,
+      format,
+      dump: pg::DumpSource::Stream(&mut |writer| project.write_dump_at_commit(commit_hash, writer)),
+      verify_checksum: verify_checksum.as_deref(),
+      terminate_connections: input.terminate_connections,
+      swap: input.swap,
+      dump_options: &dump_options,
+      before_changing_db: &mut || save_pre_restore_snapshot_if(project, input.snapshot),
+    })?,
+  };
+
+  return Ok(result);
+}
+
+/// Content checksum of the commit the restored db is compared against,
+/// warns when verifying was asked for but can't be done.
+fn restore_verification_checksum(
+  project: &Project,
+  input: &RestoreCommitInput,
+) -> ResultDynError<Option<String>> {
+  if !input.verify {
+    return Ok(None);
+  }
+
+  let verify_checksum = match project.get_dump_metadata_at_commit(input.commit_hash)? {
+    Some(metadata) => match project.engine() {
+      DbEngine::Postgresql => pg::restore_verification_checksum(&metadata)?,
+      DbEngine::Mysql => mysql::restore_verification_checksum(&metadata)?,
+    },
+    None => None,
+  };
+
+  if verify_checksum.is_none() {
+    eprintln!(
+      "Warning: restored db can't be verified against commit {}",
+      input.commit_hash
+    );
+  }
+
+  return Ok(verify_checksum);
+}
+
+/// Called by the restores right before they change the db, so a restore
+/// failing before that keeps the previous snapshot for `undo-restore`.
+fn save_pre_restore_snapshot_if(project: &Project, snapshot: bool) -> ResultDynError<()> {
+  if snapshot {
+    save_pre_restore_snapshot(project)?;
+  }
+
+  return Ok(());
+}
+
+/// Saves the project db state for `undo-restore`, unless there is no db yet.
+fn save_pre_restore_snapshot(project: &Project) -> ResultDynError<()> {
+  let db_exists = match project.engine() {
+    DbEngine::Postgresql => pg::db_exists(project.db_uri())?,
+    DbEngine::Mysql => mysql::db_exists(project.db_uri())?,
+  };
+
+  if !db_exists {
+    return Ok(());
+  }
+
+  log::debug!("Saving the db state before restoring");
+
+  dump_project_db(project)?;
+
+  let snapshot_hash = project.commit_pre_restore_snapshot()?;
+
+  eprintln!(
+    "Saved the current db state as {}, `jab project undo-restore {}` brings it back",
+    snapshot_hash,
+    project.name()
+  );
+
+  return Ok(());
+}
+
+/// Dumps the project db to `absolute_dump_path` with the tools of its engine.
+fn dump_project_db(project: &Project) -> ResultDynError<()> {
+  return match project.engine() {
+    DbEngine::Postgresql => pg::dump(pg::DumpInput {
       db_uri: project.db_uri(),
-      sql: dump,
-    })?;
+      format: project.dump_format(),
+      path: &project.absolute_dump_path(),
+      options: project.dump_options(),
+    }),
+    DbEngine::Mysql => mysql::dump(mysql::DumpInput {
+      db_uri: project.db_uri(),
+      path: &project.absolute_dump_path(),
+      options: project.dump_options(),
+    }),
+  };
+}
+
+fn warn_restore_compatibility(project: &Project, commit_hash: &str) -> ResultDynError<()> {
+  let metadata = project.get_dump_metadata_at_commit(commit_hash)?;
 
-    log::debug!("Result {}", result);
+  if let Some(metadata) = metadata {
+    if let Some(warning) = pg::restore_compatibility_warning(&metadata)? {
+      eprintln!("Warning: {}", warning);
+    }
   }
 
   return Ok(());
 }
 
+/// Parses `--since` and `--until` dates, dates without an offset are local time.
+fn parse_date(date: &str) -> ResultDynError<DateTime<FixedOffset>> {
+  if let Ok(date_time) = DateTime::parse_from_rfc3339(date) {
+    return Ok(date_time);
+  }
+
+  let naive_date_time = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").or_else(|_| {
+    return NaiveDate::parse_from_str(date, "%Y-%m-%d")
+      .map(|naive_date| naive_date.and_hms_opt(0, 0, 0).unwrap());
+  });
+
+  return naive_date_time
+    .ok()
+    .and_then(|naive_date_time| Local.from_local_datetime(&naive_date_time).earliest())
+    .map(|date_time| date_time.fixed_offset())
+    .ok_or_else(|| failure::format_err!("Invalid date {}, expected YYYY-MM-DD[ HH:MM:SS]", date));
+}
+
+const FORMAT_PLACEHOLDERS: [&str; 8] = ["%H", "%h", "%P", "%an", "%ad", "%s", "%z", "%D"];
+
+fn is_broken_pipe(err: &failure::Error) -> bool {
+  return match err.downcast_ref::<io::Error>() {
+    Some(err) => err.kind() == io::ErrorKind::BrokenPipe,
+    None => false,
+  };
+}
+
+/// `db_state` is given when the commit is what the project db holds.
+fn format_commit(
+  commit: &Commit,
+  format: &str,
+  db_state: Option<&DbState>,
+) -> ResultDynError<String> {
+  let db_marker = db_state.map_or(String::new(), |db_state| {
+    format!(
+      " (db {} {})",
+      db_state.action.as_str(),
+      db_state.time.to_rfc2822()
+    )
+  });
+
+  match format {
+    "oneline" => {
+      return Ok(format!(
+        "* {} {}{}",
+        commit.hash,
+        commit.summary(),
+        db_marker
+      ))
+    }
+    "medium" => {
+      return Ok(format!(
+        "commit {}{}\nAuthor:  {}\nDate:    {}\nParents: {}\nSize:    {} bytes\n\n    {}\n",
+        commit.hash,
+        db_marker,
+        commit.author,
+        commit.time.to_rfc2822(),
+        commit.parent_hashes.join(" "),
+        commit.dump_size()?,
+        commit.message.trim_end().replace('\n', "\n    ")
+      ));
+    }
+    _ => {}
+  }
+
+  // Scanned left to right so placeholders inside the values are kept as is
+  let mut output = String::new();
+  let mut rest = format;
+
+  while let Some(index) = rest.find('%') {
+    output.push_str(&rest[..index]);
+    rest = &rest[index..];
+
+    let placeholder = FORMAT_PLACEHOLDERS
+      .iter()
+      .find(|placeholder| rest.starts_with(*placeholder));
+    let value = match placeholder.copied() {
+      Some("%H") => commit.hash.clone(),
+      Some("%h") => String::from(&commit.hash[..7]),
+      Some("%P") => commit.parent_hashes.join(" "),
+      Some("%an") => commit.author.clone(),
+      Some("%ad") => commit.time.to_rfc2822(),
+      Some("%s") => String::from(commit.summary()),
+      Some("%z") => commit.dump_size()?.to_string(),
+      Some("%D") => db_marker.clone(),
+      _ => String::from("%"),
+    };
+
+    output.push_str(&value);
+    rest = &rest[placeholder.map_or(1, |placeholder| placeholder.len())..];
+  }
+
+  output.push_str(rest);
+
+  return Ok(output);
+}
+
+fn print_schema_diffs(diffs: Vec<SchemaObjectDiff>) {
+  if diffs.is_empty() {
+    println!("No schema changes");
+  }
+
+  let mut current_kind = None;
+
+  for diff in diffs {
+    if current_kind != Some(diff.kind) {
+      println!("{}:", diff.kind);
+      current_kind = Some(diff.kind);
+    }
+
+    match diff.change {
+      SchemaChange::Added { .. } => println!("  + {}", diff.name),
+      SchemaChange::Removed { .. } => println!("  - {}", diff.name),
+      SchemaChange::Changed { from, to } => {
+        println!("  ~ {}", diff.name);
+        println!("      - {}", from.replace('\n', "\n        "));
+        println!("      + {}", to.replace('\n', "\n        "));
+      }
+    }
+  }
+}
+
+fn print_table_diffs(diffs: Vec<TableSummaryDiff>) {
+  if diffs.is_empty() {
+    println!("No data changes");
+  }
+
+  for diff in diffs {
+    println!(
+      "{}: {} -> {} rows",
+      diff.table, diff.from_row_count, diff.to_row_count
+    );
+  }
+}
+
+fn print_data_diffs(diffs: Vec<TableDataDiff>, print_rows: bool) {
+  if diffs.is_empty() {
+    println!("No data changes");
+  }
+
+  for diff in diffs {
+    println!(
+      "{}: {} inserted, {} deleted, {} updated",
+      diff.table,
+      diff.inserted.len(),
+      diff.deleted.len(),
+      diff.updated.len()
+    );
+
+    if !print_rows {
+      continue;
+    }
+
+    for row in diff.inserted.iter() {
+      println!("  + {}", format_row(row));
+    }
+
+    for row in diff.deleted.iter() {
+      println!("  - {}", format_row(row));
+    }
+
+    for (from_row, to_row) in diff.updated.iter() {
+      // Columns on one side only are not compared by the diff, skip them.
+      let changes: Vec<String> = to_row
+        .iter()
+        .filter_map(|(column, value)| match from_row.get(column) {
+          Some(from_value) if from_value != value => Some(format!(
+            "{}: {} -> {}",
+            column,
+            display_value(from_value),
+            display_value(value)
+          )),
+          _ => None,
+        })
+        .collect();
+
+      println!("  ~ {} {}", diff.key_of(to_row), changes.join(", "));
+    }
+  }
+}
+
+fn format_row(row: &Row) -> String {
+  return row
+    .iter()
+    .map(|(column, value)| format!("{}={}", column, display_value(value)))
+    .collect::<Vec<String>>()
+    .join(" ");
+}
+
 struct MainProjectManager {
   jab_config: JabConfig,
 }
@@ -205,7 +1118,13 @@ impl MainProjectManager {
     return self.open_project(&OpenProjectInput {
       project_dir: config::get_jab_dir().as_ref(),
       project_name,
+      engine: project_config.engine,
       db_uri: &project_config.db_uri,
+      service: project_config.service.as_deref(),
+      password_env: project_config.password_env.as_deref(),
+      dump_format: project_config.dump_format,
+      terminate_connections: project_config.terminate_connections,
+      dump_options: &project_config.dump_options,
     });
   }
 }
@@ -235,12 +1154,24 @@ impl ProjectManager for MainProjectManager {
     let project = Project::create(&project::CreateInput {
       project_dir: input.project_dir,
       project_name: input.project_name,
+      engine: input.engine,
       db_uri: input.db_uri,
+      service: input.service,
+      password_env: input.password_env,
+      dump_format: input.dump_format,
+      terminate_connections: input.terminate_connections,
+      dump_options: input.dump_options,
     })?;
 
     self.jab_config.register_project_config(ProjectConfig {
       name: String::from(project.name()),
-      db_uri: String::from(project.db_uri()),
+      engine: project.engine(),
+      db_uri: String::from(input.db_uri),
+      service: input.service.map(String::from),
+      password_env: input.password_env.map(String::from),
+      dump_format: project.dump_format(),
+      terminate_connections: input.terminate_connections,
+      dump_options: project.dump_options().clone(),
     });
 
     JabConfig::persist(&self.jab_config)?;
@@ -252,7 +1183,13 @@ impl ProjectManager for MainProjectManager {
     return Project::open(&project::OpenInput {
       project_dir: input.project_dir,
       project_name: input.project_name,
+      engine: input.engine,
       db_uri: input.db_uri,
+      service: input.service,
+      password_env: input.password_env,
+      dump_format: input.dump_format,
+      terminate_connections: input.terminate_connections,
+      dump_options: input.dump_options,
     });
   }
 
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use dirs;
 use failure::Fail;
@@ -16,12 +17,160 @@ pub enum ProjectConfigError {
     name
   )]
   ProjectConfigDoesNotExist { name: String },
+
+  #[fail(
+    display = "Unknown dump format {}, expected custom, plain, directory or tables",
+    format
+  )]
+  UnknownDumpFormat { format: String },
+
+  #[fail(
+    display = "Environment variable {} with the db password is not set",
+    name
+  )]
+  PasswordEnvNotSet { name: String },
+
+  #[fail(display = "Unknown db engine {}, expected postgresql or mysql", engine)]
+  UnknownDbEngine { engine: String },
+
+  #[fail(display = "{} is not supported for {} projects", feature, engine)]
+  UnsupportedByEngine { feature: String, engine: String },
+}
+
+/// Database server of a project, dumped and restored with its own tools.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbEngine {
+  /// pg_dump, pg_restore and psql.
+  Postgresql,
+  /// mysqldump and mysql, MariaDB included.
+  Mysql,
+}
+
+/// Projects created before engines were configurable are postgres projects.
+impl Default for DbEngine {
+  fn default() -> DbEngine {
+    return DbEngine::Postgresql;
+  }
+}
+
+impl DbEngine {
+  pub fn as_str(&self) -> &'static str {
+    return match self {
+      DbEngine::Postgresql => "postgresql",
+      DbEngine::Mysql => "mysql",
+    };
+  }
+}
+
+impl FromStr for DbEngine {
+  type Err = failure::Error;
+
+  fn from_str(engine: &str) -> ResultDynError<DbEngine> {
+    return match engine {
+      "postgresql" => Ok(DbEngine::Postgresql),
+      "mysql" => Ok(DbEngine::Mysql),
+      _ => Err(
+        ProjectConfigError::UnknownDbEngine {
+          engine: String::from(engine),
+        }
+        .into(),
+      ),
+    };
+  }
+}
+
+/// How dumps are stored in the project repo.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpFormat {
+  /// `pg_dump -Fc`, compressed and only readable through pg_restore.
+  Custom,
+  /// `pg_dump -Fp`, a sql script that gives readable diffs.
+  Plain,
+  /// `pg_dump -Fd`, one file per table.
+  Directory,
+  /// `pg_dump -Fp` split into a schema file and one sql file per table
+  /// so a change to one table only touches that table file.
+  Tables,
+}
+
+/// Projects created before formats were configurable store custom format dumps.
+impl Default for DumpFormat {
+  fn default() -> DumpFormat {
+    return DumpFormat::Custom;
+  }
+}
+
+impl DumpFormat {
+  pub fn as_str(&self) -> &'static str {
+    return match self {
+      DumpFormat::Custom => "custom",
+      DumpFormat::Plain => "plain",
+      DumpFormat::Directory => "directory",
+      DumpFormat::Tables => "tables",
+    };
+  }
+}
+
+impl FromStr for DumpFormat {
+  type Err = failure::Error;
+
+  fn from_str(format: &str) -> ResultDynError<DumpFormat> {
+    return match format {
+      "custom" => Ok(DumpFormat::Custom),
+      "plain" => Ok(DumpFormat::Plain),
+      "directory" => Ok(DumpFormat::Directory),
+      "tables" => Ok(DumpFormat::Tables),
+      _ => Err(
+        ProjectConfigError::UnknownDumpFormat {
+          format: String::from(format),
+        }
+        .into(),
+      ),
+    };
+  }
+}
+
+/// Filters and flags passed to pg_dump, table and schema names accept
+/// the pg_dump patterns, e.g. `audit.*`.
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DumpOptions {
+  pub include_schemas: Vec<String>,
+  pub exclude_schemas: Vec<String>,
+  pub include_tables: Vec<String>,
+  pub exclude_tables: Vec<String>,
+  /// Tables whose definition is dumped without their rows, e.g. logs or sessions.
+  pub exclude_table_data: Vec<String>,
+  pub no_owner: bool,
+  pub no_acl: bool,
 }
 
 #[derive(Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ProjectConfig {
   pub name: String,
+  #[serde(default)]
+  pub engine: DbEngine,
+  /// Libpq connection uri or string, keywords it leaves unset are read
+  /// from the service and the `PG*` environment variables.
+  #[serde(default)]
   pub db_uri: String,
+  /// Name of a service in `~/.pg_service.conf` or the system `pg_service.conf`.
+  #[serde(default)]
+  pub service: Option<String>,
+  /// Environment variable the db password is read from, otherwise
+  /// `PGPASSWORD` or `~/.pgpass` are used as usual.
+  #[serde(default)]
+  pub password_env: Option<String>,
+  #[serde(default)]
+  pub dump_format: DumpFormat,
+  /// Whether restore terminates the sessions connected to the db before
+  /// dropping it, unset means only when the db is on this machine.
+  #[serde(default)]
+  pub terminate_connections: Option<bool>,
+  #[serde(default)]
+  pub dump_options: DumpOptions,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -1 +1,4 @@
+pub mod mysql;
 pub mod postgresql;
+pub mod process;
+pub mod uri;
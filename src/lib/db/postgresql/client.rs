@@ -1,108 +1,590 @@
+use std::fmt;
 use std::fs;
+use std::fs::File;
 use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
 
 use failure::Fail;
+use sha2::Digest;
+use sha2::Sha256;
 
+use crate::config::DumpFormat;
+use crate::config::DumpOptions;
+use crate::db::postgresql::connection;
+use crate::db::postgresql::connection::ConnectionConfig;
+use crate::db::postgresql::connection::ConnectionConfigError;
+use crate::db::postgresql::table_files;
+use crate::db::postgresql::table_selection::SelectedDataWriter;
+use crate::db::postgresql::table_selection::TableSelection;
+use crate::db::process::read_in_background;
+use crate::db::process::run_with_stdin;
+use crate::db::process::run_with_stdout;
+use crate::db::process::stderr_message;
+use crate::db::uri::RedactedDbUri;
+use crate::dump_metadata::DumpMetadata;
 use crate::types::ResultDynError;
 
 pub struct DumpInput<'a> {
   pub db_uri: &'a str,
+  pub format: DumpFormat,
+  /// File, or directory for the directory and tables formats, the dump is written to.
+  pub path: &'a Path,
+  pub options: &'a DumpOptions,
 }
 
-pub fn dump(input: DumpInput) -> ResultDynError<Vec<u8>> {
-  let output = Command::new("pg_dump")
-    .arg(format!("postgres://{}", input.db_uri))
-    .arg("-Fc")
-    .output()?;
+pub fn dump(input: DumpInput) -> ResultDynError<()> {
+  if input.format == DumpFormat::Tables {
+    return dump_table_files(input);
+  }
+
+  let mut command = DbConnectionConfig::from(input.db_uri)?.command("pg_dump");
+
+  command
+    .arg(format!("--file={}", input.path.display()))
+    .args(dump_option_args(input.options));
+
+  match input.format {
+    DumpFormat::Custom => {
+      command.arg("--format=c");
+    }
+    DumpFormat::Plain => {
+      command.arg("--format=p");
+    }
+    DumpFormat::Directory => {
+      // pg_dump refuses to write into an existing directory, uncompressed
+      // table files keep the git history diffable.
+      if input.path.exists() {
+        fs::remove_dir_all(input.path)?;
+      }
+
+      command.arg("--format=d").arg("--compress=0");
+    }
+    DumpFormat::Tables => unreachable!("table files are split by dump_table_files"),
+  }
+
+  return check_dump_output(command.output()?);
+}
 
+/// A single plain dump split while it is read, so every table
+/// file comes from the same snapshot.
+fn dump_table_files(input: DumpInput) -> ResultDynError<()> {
+  let mut child = DbConnectionConfig::from(input.db_uri)?
+    .command("pg_dump")
+    .arg("--format=p")
+    .args(dump_option_args(input.options))
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+
+  // Drained while the dump is split so pg_dump can't block on a full stderr pipe
+  let stderr_reader = read_in_background(child.stderr.take().unwrap());
+  // Drops stdout once done, a failed split stops pg_dump instead of blocking it
+  let written = table_files::write(BufReader::new(child.stdout.take().unwrap()), input.path);
+  let status = child.wait()?;
+  let stderr = stderr_reader.join().unwrap()?;
+
+  written?;
+
+  return check_dump_output(Output {
+    status,
+    stdout: vec![],
+    stderr,
+  });
+}
+
+fn dump_option_args(options: &DumpOptions) -> Vec<String> {
+  let patterns = [
+    ("--schema", &options.include_schemas),
+    ("--exclude-schema", &options.exclude_schemas),
+    ("--table", &options.include_tables),
+    ("--exclude-table", &options.exclude_tables),
+    ("--exclude-table-data", &options.exclude_table_data),
+  ];
+  let mut args: Vec<String> = patterns
+    .iter()
+    .flat_map(|(flag, values)| {
+      values
+        .iter()
+        .map(move |value| format!("{}={}", flag, value))
+    })
+    .collect();
+
+  args.extend(restore_option_args(options));
+
+  return args;
+}
+
+/// Flags pg_dump and pg_restore share, pg_restore applies them to dumps
+/// made before they were set.
+fn restore_option_args(options: &DumpOptions) -> Vec<String> {
+  let mut args = vec![];
+
+  if options.no_owner {
+    args.push(String::from("--no-owner"));
+  }
+
+  if options.no_acl {
+    args.push(String::from("--no-acl"));
+  }
+
+  return args;
+}
+
+/// pg_dump reports warnings such as a server version mismatch on stderr,
+/// those are treated as failures too.
+fn check_dump_output(output: Output) -> ResultDynError<()> {
   let err = String::from_utf8(output.stderr)?;
 
-  if err.len() > 0 {
-    let err = io::Error::new(io::ErrorKind::Other, err);
+  if !output.status.success() || !err.is_empty() {
+    return Err(io::Error::other(err).into());
+  }
+
+  return Ok(());
+}
+
+pub enum SqlContent {
+  Full,
+  SchemaOnly,
+  DataOnly,
+}
+
+pub struct ToSqlInput<'a> {
+  pub dump_path: &'a Path,
+  pub format: DumpFormat,
+  pub content: SqlContent,
+}
+
+/// Converts a dump into a plain SQL script by running it through
+/// `pg_restore`, no db connection is needed. Plain dumps already are
+/// a script and are returned whole regardless of the requested content.
+pub fn to_sql(input: ToSqlInput) -> ResultDynError<String> {
+  let mut sql = vec![];
+
+  write_sql(input, &mut sql)?;
+
+  return Ok(String::from_utf8(sql)?);
+}
+
+/// Same as `to_sql` but streams the script into `writer`.
+pub fn write_sql(input: ToSqlInput, writer: &mut dyn Write) -> ResultDynError<()> {
+  if input.format == DumpFormat::Plain {
+    io::copy(&mut File::open(input.dump_path)?, writer)?;
+
+    return Ok(());
+  }
+
+  if input.format == DumpFormat::Tables {
+    let data_dir = input.dump_path.join(table_files::DATA_DIR);
+
+    for file_path in table_files::files(input.dump_path)? {
+      let is_data = file_path.starts_with(&data_dir);
+      let is_included = match input.content {
+        SqlContent::Full => true,
+        SqlContent::SchemaOnly => !is_data,
+        SqlContent::DataOnly => is_data,
+      };
+
+      if is_included {
+        io::copy(&mut File::open(file_path)?, writer)?;
+      }
+    }
+
+    return Ok(());
+  }
+
+  let mut command = Command::new("pg_restore");
+
+  command.arg("--file=-");
 
-    return Err(err.into());
+  match input.content {
+    SqlContent::Full => {}
+    SqlContent::SchemaOnly => {
+      command.arg("--schema-only");
+    }
+    SqlContent::DataOnly => {
+      command.arg("--data-only");
+    }
   }
 
-  return Ok(output.stdout);
+  command.arg(input.dump_path);
+
+  return run_with_stdout(command, writer);
+}
+
+pub struct DumpMetadataInput<'a> {
+  pub db_uri: &'a str,
+  pub format: DumpFormat,
+  pub dump_path: &'a Path,
+  /// The options the dump was made with.
+  pub options: &'a DumpOptions,
+}
+
+/// Builds the provenance metadata of a dump, versions are read from the
+/// archive header printed by `pg_restore --list` or from the header
+/// comments of a plain dump.
+pub fn dump_metadata(input: DumpMetadataInput) -> ResultDynError<DumpMetadata> {
+  let db_connection_config = DbConnectionConfig::from(input.db_uri)?;
+  let (header, header_prefix) = match input.format {
+    DumpFormat::Plain => (read_sql_header(input.dump_path)?, "-- "),
+    DumpFormat::Tables => (
+      read_sql_header(&input.dump_path.join(table_files::SCHEMA_FILE))?,
+      "-- ",
+    ),
+    _ => {
+      let mut command = Command::new("pg_restore");
+      let mut toc = vec![];
+
+      command.arg("--list").arg(input.dump_path);
+      run_with_stdout(command, &mut toc)?;
+
+      (String::from_utf8(toc)?, ";     ")
+    }
+  };
+
+  let header_value = |key: &str| {
+    let prefix = format!("{}{}", header_prefix, key);
+
+    return header
+      .lines()
+      .find_map(|line| line.strip_prefix(&prefix))
+      .map(|value| String::from(value.trim_start_matches(':').trim()))
+      .unwrap_or_default();
+  };
+
+  let connection_config = &db_connection_config.connection_config;
+  let host = match connection_config.port() {
+    Some(port) => format!("{}:{}", connection_config.get("host").unwrap_or(""), port),
+    None => String::from(connection_config.get("host").unwrap_or("")),
+  };
+  let (size, checksum) = dump_checksum(input.dump_path, input.format)?;
+
+  return Ok(DumpMetadata {
+    server_version: header_value("Dumped from database version"),
+    dump_tool_version: header_value("Dumped by pg_dump version"),
+    host,
+    db_name: db_connection_config.db_name,
+    format: String::from(input.format.as_str()),
+    size,
+    checksum,
+    content_checksum: Some(content_checksum(input.dump_path, input.format)?),
+    dump_options: Some(input.options.clone()),
+  });
+}
+
+/// The leading comments of a plain dump, where pg_dump writes the versions.
+fn read_sql_header(path: &Path) -> ResultDynError<String> {
+  let mut header = String::new();
+
+  for line in BufReader::new(File::open(path)?).lines() {
+    let line = line?;
+
+    if !line.is_empty() && !line.starts_with("--") && !line.starts_with('\\') {
+      break;
+    }
+
+    header.push_str(&line);
+    header.push('\n');
+  }
+
+  return Ok(header);
+}
+
+/// Size and hex encoded sha256 of the dump files, for dumps made of several
+/// files every file path is hashed followed by its content in restore order.
+fn dump_checksum(dump_path: &Path, format: DumpFormat) -> ResultDynError<(u64, String)> {
+  let mut hasher = Sha256::new();
+  let file_paths = match format {
+    DumpFormat::Custom | DumpFormat::Plain => {
+      let size = io::copy(&mut File::open(dump_path)?, &mut hasher)?;
+
+      return Ok((size, format!("{:x}", hasher.finalize())));
+    }
+    DumpFormat::Directory => {
+      let mut file_paths: Vec<PathBuf> = fs::read_dir(dump_path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, io::Error>>()?;
+      file_paths.sort();
+
+      file_paths
+    }
+    DumpFormat::Tables => table_files::files(dump_path)?,
+  };
+
+  let mut size = 0;
+
+  for file_path in file_paths {
+    hasher.update(
+      file_path
+        .strip_prefix(dump_path)?
+        .to_string_lossy()
+        .as_bytes(),
+    );
+    size += io::copy(&mut File::open(&file_path)?, &mut hasher)?;
+  }
+
+  return Ok((size, format!("{:x}", hasher.finalize())));
+}
+
+/// Custom format dumps embed the dump time and are compressed so the bytes
+/// differ on every dump, the sql rendered by pg_restore does not.
+fn content_checksum(dump_path: &Path, format: DumpFormat) -> ResultDynError<String> {
+  let mut hasher = CanonicalSqlHasher::default();
+
+  write_sql(
+    ToSqlInput {
+      dump_path,
+      format,
+      content: SqlContent::Full,
+    },
+    &mut hasher,
+  )?;
+
+  return Ok(hasher.finish().checksum);
+}
+
+/// Hashes a sql script line by line without the lines that change between
+/// runs, newer versions wrap the script with `\restrict <random key>`, and
+/// without the version comments so upgrading the server or pg_dump keeps the
+/// checksum. Lines are joined with `\n` without a trailing newline.
+#[derive(Default)]
+struct CanonicalSqlHasher {
+  hasher: Sha256,
+  has_lines: bool,
+  /// Keeps the version comments like commits made before they were skipped.
+  legacy_hasher: Sha256,
+  has_legacy_lines: bool,
+  line: Vec<u8>,
+}
+
+/// Content checksum of a plain sql script, see `CanonicalSqlHasher`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentChecksum {
+  pub checksum: String,
+  /// Checksum with the version comments, as recorded by older commits.
+  legacy_checksum: String,
+}
+
+impl ContentChecksum {
+  /// Whether the content checksum recorded by a commit is this content.
+  pub fn matches(&self, content_checksum: &str) -> bool {
+    return self.checksum == content_checksum || self.legacy_checksum == content_checksum;
+  }
+}
+
+impl CanonicalSqlHasher {
+  fn hash_line(&mut self) {
+    let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+    let is_noise = line.starts_with(b"\\restrict ") || line.starts_with(b"\\unrestrict ");
+    let is_version = line.starts_with(b"-- Dumped from database version ")
+      || line.starts_with(b"-- Dumped by pg_dump version ");
+
+    if !is_noise {
+      if self.has_legacy_lines {
+        self.legacy_hasher.update(b"\n");
+      }
+
+      self.legacy_hasher.update(line);
+      self.has_legacy_lines = true;
+    }
+
+    if !is_noise && !is_version {
+      if self.has_lines {
+        self.hasher.update(b"\n");
+      }
+
+      self.hasher.update(line);
+      self.has_lines = true;
+    }
+
+    self.line.clear();
+  }
+
+  fn finish(mut self) -> ContentChecksum {
+    if !self.line.is_empty() {
+      self.hash_line();
+    }
+
+    return ContentChecksum {
+      checksum: format!("{:x}", self.hasher.finalize()),
+      legacy_checksum: format!("{:x}", self.legacy_hasher.finalize()),
+    };
+  }
+}
+
+impl Write for CanonicalSqlHasher {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    for chunk in buf.split_inclusive(|byte| *byte == b'\n') {
+      match chunk.strip_suffix(b"\n") {
+        Some(line) => {
+          self.line.extend_from_slice(line);
+          self.hash_line();
+        }
+        None => self.line.extend_from_slice(chunk),
+      }
+    }
+
+    return Ok(buf.len());
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    return Ok(());
+  }
+}
+
+/// pg_restore can't read archives made by a newer pg_dump, returns
+/// a warning message when the installed pg_restore is older than
+/// the pg_dump that created the dump.
+pub fn restore_compatibility_warning(metadata: &DumpMetadata) -> ResultDynError<Option<String>> {
+  match metadata.format.parse()? {
+    // Plain dumps are restored with psql
+    DumpFormat::Plain | DumpFormat::Tables => return Ok(None),
+    DumpFormat::Custom | DumpFormat::Directory => {}
+  }
+
+  let output = Command::new("pg_restore").arg("--version").output()?;
+  let output = String::from_utf8(output.stdout)?;
+
+  // Output looks like `pg_restore (PostgreSQL) 12.1`
+  let pg_restore_version = output.split_whitespace().nth(2).unwrap_or("");
+
+  let is_incompatible = match (
+    major_version(pg_restore_version),
+    major_version(&metadata.dump_tool_version),
+  ) {
+    (Some(pg_restore_major), Some(pg_dump_major)) => pg_restore_major < pg_dump_major,
+    _ => false,
+  };
+
+  if !is_incompatible {
+    return Ok(None);
+  }
+
+  return Ok(Some(format!(
+    "dump was created by pg_dump {} but pg_restore is {}, restore will likely fail",
+    metadata.dump_tool_version, pg_restore_version
+  )));
+}
+
+/// Major version of a postgres version string, e.g. `9.6` for `9.6.3`
+/// and `12` for `12.1 (Debian 12.1-1)`.
+fn major_version(version: &str) -> Option<(u32, u32)> {
+  let mut parts = version.split_whitespace().next()?.split('.');
+  let major: u32 = parts.next()?.parse().ok()?;
+
+  if major >= 10 {
+    return Some((major, 0));
+  }
+
+  return Some((major, parts.next()?.parse().ok()?));
+}
+
+/// Where `restore` reads the dump from.
+pub enum DumpSource<'a> {
+  /// Dump file, or directory for the directory and tables formats.
+  Path(&'a Path),
+  /// Writes the dump into the restore tool stdin, the tables format is
+  /// written as its files concatenated in restore order. Directory
+  /// format dumps can only be restored from a path.
+  Stream(&'a mut dyn FnMut(&mut dyn Write) -> ResultDynError<()>),
 }
 
 pub struct RestoreInput<'a> {
   pub db_uri: &'a str,
-  pub sql: Vec<u8>,
+  pub format: DumpFormat,
+  pub dump: DumpSource<'a>,
+  /// Content checksum the restored db must match, see `restore_verification_checksum`.
+  pub verify_checksum: Option<&'a str>,
+  /// Blocks new connections and terminates the sessions connected to the db
+  /// so it can be dropped, see `active_sessions` to show them beforehand.
+  pub terminate_connections: bool,
+  /// Restores into a scratch db then swaps it with the db, see `restore`.
+  pub swap: bool,
+  /// The options the dump was made with, see `restore_option_args`.
+  pub dump_options: &'a DumpOptions,
+  /// Called right before the db is first changed, e.g. to save a snapshot
+  /// of it, so a restore failing before that leaves the snapshot alone.
+  pub before_changing_db: &'a mut dyn FnMut() -> ResultDynError<()>,
+}
+
+/// A backend connected to the db as listed in `pg_stat_activity`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbSession {
+  pub pid: String,
+  pub username: String,
+  pub application_name: String,
+  /// Empty for unix socket connections.
+  pub client_addr: String,
+  pub state: String,
+  pub backend_start: String,
+}
+
+impl fmt::Display for DbSession {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let client_addr = if self.client_addr.is_empty() {
+      "local socket"
+    } else {
+      &self.client_addr
+    };
+
+    return write!(
+      f,
+      "pid {} {} from {} ({}) {}, connected since {}",
+      self.pid, self.username, client_addr, self.application_name, self.state, self.backend_start
+    );
+  }
 }
 
+/// A connection to a single db with the keywords libpq would default filled in,
+/// restoring drops and recreates the db by name so the name has to be known.
 #[derive(Debug)]
 struct DbConnectionConfig {
+  connection_config: ConnectionConfig,
   db_name: String,
-  host: String,
-  port: Option<String>,
-  username: String,
-  password: Option<String>,
 }
 
 #[derive(Debug, Clone, Fail)]
 pub enum DbError {
-  #[fail(
-    display = "Error when parsing db uri {}, parsing step: {}. {:?}",
-    db_uri, parsing_step, message
-  )]
-  DbUriParseError {
-    parsing_step: String,
-    db_uri: String,
-    message: Option<String>,
-  },
-
   #[fail(
     display = "Error when restoring db uri {}, restore step: {}. {:?}",
     db_uri, restore_step, message
   )]
   DbRestoreError {
     restore_step: String,
-    db_uri: String,
+    db_uri: RedactedDbUri,
     message: Option<String>,
   },
+
+  #[fail(display = "Directory format dumps can only be restored from a path")]
+  DirectoryDumpStreamError,
 }
 
 impl DbError {
-  fn parse_error(db_uri: &str, step: DbUriParsingStep, message: Option<String>) -> DbError {
-    return DbError::DbUriParseError {
-      db_uri: String::from(db_uri),
-      parsing_step: step.to_string(),
-      message,
-    };
-  }
-
   fn restore_error(db_uri: &str, step: DbRestoreStep, message: Option<String>) -> DbError {
     return DbError::DbRestoreError {
-      db_uri: String::from(db_uri),
+      db_uri: RedactedDbUri::new(db_uri),
       restore_step: step.to_string(),
       message,
     };
   }
 }
 
-#[derive(Debug)]
-enum DbUriParsingStep {
-  DbName,
-  CredentialAndHostCandidate,
-  HostAndPort,
-  Host,
-  Credential,
-  CredentialUsername,
-}
-
-impl ToString for DbUriParsingStep {
-  fn to_string(&self) -> String {
-    return format!("{:?}", self);
-  }
-}
-
 #[derive(Debug)]
 enum DbRestoreStep {
   DropDb,
   CreateDb,
+  TerminateConnections,
   RestoreDb,
+  VerifyRestore,
+  SwapDb,
 }
 
 impl ToString for DbRestoreStep {
@@ -112,206 +594,955 @@ impl ToString for DbRestoreStep {
 }
 
 impl DbConnectionConfig {
-  fn from(db_uri: &str) -> ResultDynError<DbConnectionConfig> {
-    let parts: Vec<&str> = db_uri.split('/').collect();
-    let db_name = parts
-      .get(1)
-      .ok_or({ DbError::parse_error(db_uri, DbUriParsingStep::DbName, None) })?;
+  /// Hosts where terminating other sessions only affects the developer's own apps.
+  fn is_local_host(&self) -> bool {
+    return self.connection_config.is_local();
+  }
 
-    let target_str = String::from(*parts.get(0).ok_or({
-      DbError::parse_error(db_uri, DbUriParsingStep::CredentialAndHostCandidate, None)
-    })?);
+  /// A libpq tool connected to the db, the password is passed through
+  /// the environment so it doesn't show up in the process list.
+  fn command(&self, program: &str) -> Command {
+    let (mut command, connection_string) = self.command_with_connection_string(program);
 
-    let parts: Vec<&str> = target_str.split('@').collect();
-    let credential_str = String::from(
-      *parts
-        .get(0)
-        .ok_or({ DbError::parse_error(db_uri, DbUriParsingStep::Credential, None) })?,
-    );
-    let host_and_port = parts
-      .get(1)
-      .ok_or({ DbError::parse_error(db_uri, DbUriParsingStep::HostAndPort, None) })?;
-
-    let host_and_port_parts: Vec<&str> = host_and_port.split(':').collect();
-    let host = host_and_port_parts
-      .get(0)
-      .ok_or({ DbError::parse_error(db_uri, DbUriParsingStep::Host, None) })?;
-    let port = host_and_port_parts.get(1).map(|port| (*port).into());
-
-    let parts: Vec<&str> = credential_str.split(':').collect();
-    let username = parts
-      .get(0)
-      .ok_or({ DbError::parse_error(db_uri, DbUriParsingStep::CredentialUsername, None) })?;
-    let password = parts.get(1).map(|pass| (*pass).into());
+    command.arg(format!("--dbname={}", connection_string));
+
+    return command;
+  }
+
+  /// A `createdb` or `dropdb` command connected to the `postgres` maintenance
+  /// db, the db name to create or drop goes last.
+  fn maintenance_command(&self, program: &str) -> Command {
+    let (mut command, connection_string) = self
+      .with_db_name("postgres")
+      .command_with_connection_string(program);
+
+    command.arg(format!("--maintenance-db={}", connection_string));
+
+    return command;
+  }
+
+  fn command_with_connection_string(&self, program: &str) -> (Command, String) {
+    let mut command = Command::new(program);
+    let mut connection_config = self.connection_config.clone();
+
+    if let Some(password) = connection_config.password() {
+      command.env("PGPASSWORD", password);
+    }
+
+    connection_config.remove("password");
+    // Fails instead of prompting when neither the db uri, the environment
+    // nor ~/.pgpass has the password, restores pipe the dump through stdin
+    command.arg("--no-password");
+
+    return (command, connection_config.to_connection_string());
+  }
+
+  fn with_db_name(&self, db_name: &str) -> DbConnectionConfig {
+    return DbConnectionConfig {
+      connection_config: self.connection_config.with_db_name(db_name),
+      db_name: String::from(db_name),
+    };
+  }
+
+  /// Runs the sql with psql connected to the `postgres` maintenance db, rows
+  /// are returned unaligned with tab separated columns.
+  fn run_maintenance_sql(&self, sql: &str) -> ResultDynError<String> {
+    let mut command = self.with_db_name("postgres").command("psql");
+
+    command
+      .arg("--no-psqlrc")
+      .arg("--tuples-only")
+      .arg("--no-align")
+      .arg("--field-separator=\t")
+      .arg("--set=ON_ERROR_STOP=1")
+      .arg(format!("--command={}", sql));
+
+    let mut output = vec![];
+    run_with_stdout(command, &mut output)?;
+
+    return Ok(String::from_utf8(output)?);
+  }
+
+  fn quoted_db_name_literal(&self) -> String {
+    return format!("'{}'", self.db_name.replace('\'', "''"));
+  }
+
+  fn quoted_db_name_identifier(&self) -> String {
+    return quote_identifier(&self.db_name);
+  }
+
+  fn db_exists(&self) -> ResultDynError<bool> {
+    let db_count = self.run_maintenance_sql(&format!(
+      "SELECT count(*) FROM pg_database WHERE datname = {}",
+      self.quoted_db_name_literal()
+    ))?;
+
+    return Ok(db_count.trim() != "0");
+  }
+
+  fn drop_db(&self) -> ResultDynError<()> {
+    terminate_connections(self)?;
+
+    self.run_maintenance_sql(&format!(
+      "DROP DATABASE IF EXISTS {}",
+      self.quoted_db_name_identifier()
+    ))?;
+
+    return Ok(());
+  }
+}
+
+fn quote_identifier(identifier: &str) -> String {
+  return format!("\"{}\"", identifier.replace('"', "\"\""));
+}
+
+/// The db uri pointing to another db on the same server with the same credentials.
+pub fn db_uri_with_db_name(db_uri: &str, db_name: &str) -> ResultDynError<String> {
+  return connection::db_uri_with_db_name(db_uri, db_name);
+}
+
+/// The db uri connecting through the pg service, keywords set
+/// in the db uri take precedence over the service definition.
+pub fn db_uri_with_service(db_uri: &str, service: &str) -> ResultDynError<String> {
+  let mut connection_config = ConnectionConfig::parse(db_uri)?;
+
+  connection_config.set("service", service);
+
+  return Ok(connection_config.to_connection_string());
+}
+
+/// Host, port and name of the db without the credentials, the same
+/// for every db uri connecting to the db.
+pub fn db_identity(db_uri: &str) -> ResultDynError<String> {
+  return Ok(
+    DbConnectionConfig::from(db_uri)?
+      .connection_config
+      .identity()
+      .to_connection_string(),
+  );
+}
+
+/// The db uri with the password when it has none, the pg tools are given
+/// the password through the environment of each command.
+pub fn db_uri_with_password(db_uri: &str, password: &str) -> ResultDynError<String> {
+  let mut connection_config = ConnectionConfig::parse(db_uri)?;
+
+  if connection_config.password().is_some() {
+    return Ok(String::from(db_uri));
+  }
+
+  connection_config.set("password", password);
+
+  return Ok(connection_config.to_connection_string());
+}
+
+/// Whether the db is on this machine, where `restore` terminates
+/// the sessions connected to the db by default.
+pub fn is_local_db(db_uri: &str) -> ResultDynError<bool> {
+  return Ok(DbConnectionConfig::from(db_uri)?.is_local_host());
+}
+
+pub fn db_exists(db_uri: &str) -> ResultDynError<bool> {
+  return DbConnectionConfig::from(db_uri)?.db_exists();
+}
+
+/// Sessions connected to the db, other than our own.
+pub fn active_sessions(db_uri: &str) -> ResultDynError<Vec<DbSession>> {
+  let db_connection_config = DbConnectionConfig::from(db_uri)?;
+  let rows = db_connection_config.run_maintenance_sql(&format!(
+    "SELECT pid, usename, application_name, client_addr, state, date_trunc('second', backend_start) \
+     FROM pg_stat_activity WHERE datname = {} AND pid <> pg_backend_pid() ORDER BY backend_start",
+    db_connection_config.quoted_db_name_literal()
+  ))?;
+
+  return Ok(
+    rows
+      .lines()
+      .filter(|row| !row.is_empty())
+      .map(|row| {
+        let mut columns = row.split('\t').map(String::from);
+        let mut column = || columns.next().unwrap_or_default();
+
+        return DbSession {
+          pid: column(),
+          username: column(),
+          application_name: column(),
+          client_addr: column(),
+          state: column(),
+          backend_start: column(),
+        };
+      })
+      .collect(),
+  );
+}
+
+/// Blocks new connections to the db then terminates the connected sessions,
+/// waits until they are gone since terminating only signals the backends.
+fn terminate_connections(db_connection_config: &DbConnectionConfig) -> ResultDynError<()> {
+  // Restoring into a new db, there is nothing to terminate
+  if !db_connection_config.db_exists()? {
+    return Ok(());
+  }
+
+  db_connection_config.run_maintenance_sql(&format!(
+    "ALTER DATABASE {} ALLOW_CONNECTIONS false",
+    db_connection_config.quoted_db_name_identifier()
+  ))?;
+
+  let session_count_sql = format!(
+    "SELECT count(pg_terminate_backend(pid)) FROM pg_stat_activity \
+     WHERE datname = {} AND pid <> pg_backend_pid()",
+    db_connection_config.quoted_db_name_literal()
+  );
+
+  for _ in 0..50 {
+    let session_count = db_connection_config.run_maintenance_sql(&session_count_sql)?;
+
+    if session_count.trim() == "0" {
+      return Ok(());
+    }
+
+    thread::sleep(Duration::from_millis(100));
+  }
+
+  return Err(io::Error::other("connected sessions did not terminate in time").into());
+}
+
+fn allow_connections(db_connection_config: &DbConnectionConfig) -> ResultDynError<()> {
+  db_connection_config.run_maintenance_sql(&format!(
+    "ALTER DATABASE {} ALLOW_CONNECTIONS true",
+    db_connection_config.quoted_db_name_identifier()
+  ))?;
+
+  return Ok(());
+}
+
+impl DbConnectionConfig {
+  fn from(db_uri: &str) -> ResultDynError<DbConnectionConfig> {
+    let connection_config = ConnectionConfig::parse(db_uri)?.with_defaults()?;
+    let db_name = String::from(connection_config.db_name().ok_or_else(|| {
+      ConnectionConfigError::MissingDbNameError {
+        db_uri: RedactedDbUri::new(db_uri),
+      }
+    })?);
 
     return Ok(DbConnectionConfig {
-      db_name: (*db_name).into(),
-      username: (*username).into(),
-      password,
-      host: (*host).into(),
-      port,
+      connection_config,
+      db_name,
     });
   }
 }
 
 /// The cleaniest way to do clean restore is by doing below steps:
+/// * Terminate connected sessions when `terminate_connections` is set.
 /// * Drop DB.
 /// * Create DB.
-/// * Run pg_restore.
+/// * Run pg_restore, or psql for plain dumps.
+/// * Dump the restored DB and compare it against `verify_checksum`.
+///
+/// Dropping the whole DB removes every object regardless of dependencies,
+/// and dumps create foreign keys, indexes and triggers after the data is
+/// loaded, so foreign keys don't need `ON DELETE CASCADE` and tables can
+/// reference each other in any order.
+///
+/// Streamed dumps are piped into the restore tool stdin so
+/// the whole dump is never held in memory.
+///
+/// With `swap` the dump is restored into a scratch db first so a failed
+/// restore never leaves us without the db, see `restore_with_swap`.
 ///
 /// Note that maybe this will change in the future, we would
 /// probably need to write our own version of pg_restore and pg_dump.
 pub fn restore(input: RestoreInput) -> ResultDynError<String> {
+  if input.swap {
+    return restore_with_swap(input);
+  }
+
+  return restore_in_place(input);
+}
+
+/// Restores into a scratch db next to the db, then renames the db aside
+/// and the scratch db into its place in a single transaction and drops
+/// the old db. The db is left untouched when any step before the swap fails,
+/// at the cost of holding both dbs on the server during the restore.
+fn restore_with_swap(mut input: RestoreInput) -> ResultDynError<String> {
   let db_uri = input.db_uri;
   let db_connection_config = DbConnectionConfig::from(db_uri)?;
-  let temp_file_path = "/tmp/jab.sql";
+  let scratch_db_name = format!("{}_jab_restore", db_connection_config.db_name);
+  let previous_db_name = format!("{}_jab_previous", db_connection_config.db_name);
+  let scratch_db_uri = db_uri_with_db_name(db_uri, &scratch_db_name)?;
+  let scratch_db_connection_config = DbConnectionConfig::from(&scratch_db_uri)?;
+  let previous_db_connection_config =
+    DbConnectionConfig::from(&db_uri_with_db_name(db_uri, &previous_db_name)?)?;
+
+  log::debug!("Restoring into scratch DB {}", scratch_db_name);
+
+  let restore_result = restore_in_place(RestoreInput {
+    db_uri: &scratch_db_uri,
+    format: input.format,
+    dump: match &mut input.dump {
+      DumpSource::Path(dump_path) => DumpSource::Path(dump_path),
+      DumpSource::Stream(write_dump) => DumpSource::Stream(*write_dump),
+    },
+    verify_checksum: input.verify_checksum,
+    // A scratch db left behind by an interrupted restore is nobody's
+    terminate_connections: true,
+    swap: false,
+    dump_options: input.dump_options,
+    // Only the scratch db is changed so far
+    before_changing_db: &mut || Ok(()),
+  });
+
+  let output = match restore_result {
+    Ok(output) => output,
+    Err(err) => {
+      warn_on_cleanup_error(
+        scratch_db_connection_config.drop_db(),
+        &format!("drop scratch db {}", scratch_db_name),
+      );
+
+      return Err(err);
+    }
+  };
+
+  if let Err(err) = (input.before_changing_db)() {
+    warn_on_cleanup_error(
+      scratch_db_connection_config.drop_db(),
+      &format!("drop scratch db {}", scratch_db_name),
+    );
+
+    return Err(err);
+  }
+
+  log::debug!("Swapping DB with scratch DB");
+
+  let swap_result = (|| -> ResultDynError<()> {
+    let db_exists = db_connection_config.db_exists()?;
+
+    // Left behind when dropping it failed after a previous swap
+    previous_db_connection_config.drop_db()?;
+
+    if db_exists && input.terminate_connections {
+      terminate_connections(&db_connection_config)?;
+    }
+
+    let mut sql = format!(
+      "ALTER DATABASE {} RENAME TO {};",
+      scratch_db_connection_config.quoted_db_name_identifier(),
+      db_connection_config.quoted_db_name_identifier()
+    );
+
+    if db_exists {
+      sql = format!(
+        "ALTER DATABASE {} RENAME TO {}; {}",
+        db_connection_config.quoted_db_name_identifier(),
+        previous_db_connection_config.quoted_db_name_identifier(),
+        sql
+      );
+    }
+
+    // psql runs a multi statement command in a single transaction
+    db_connection_config.run_maintenance_sql(&sql)?;
+
+    return Ok(());
+  })();
+
+  if let Err(err) = swap_result {
+    if input.terminate_connections {
+      warn_on_cleanup_error(
+        db_connection_config
+          .db_exists()
+          .and_then(|db_exists| match db_exists {
+            true => allow_connections(&db_connection_config),
+            false => Ok(()),
+          }),
+        "allow connections to the db again",
+      );
+    }
+
+    warn_on_cleanup_error(
+      scratch_db_connection_config.drop_db(),
+      &format!("drop scratch db {}", scratch_db_name),
+    );
+
+    return Err(
+      DbError::restore_error(db_uri, DbRestoreStep::SwapDb, Some(err.to_string())).into(),
+    );
+  }
+
+  // The next swap restore drops it when this fails
+  warn_on_cleanup_error(
+    previous_db_connection_config.drop_db(),
+    &format!("drop previous db {}", previous_db_name),
+  );
+
+  return Ok(output);
+}
+
+/// Cleanup failures are only logged so they don't replace the restore result.
+fn warn_on_cleanup_error(result: ResultDynError<()>, cleanup: &str) {
+  if let Err(err) = result {
+    log::warn!("Could not {}: {}", cleanup, err);
+  }
+}
+
+pub struct RestoreTablesInput<'a> {
+  pub db_uri: &'a str,
+  pub dump_path: &'a Path,
+  pub format: DumpFormat,
+  /// `table` or `schema.table` names.
+  pub tables: &'a [String],
+  pub schemas: &'a [String],
+  /// Called once the selected tables are found in the dump, right before
+  /// their rows are replaced, see `RestoreInput::before_changing_db`.
+  pub before_changing_db: &'a mut dyn FnMut() -> ResultDynError<()>,
+}
+
+/// Replaces the rows of the selected tables with the rows stored in the dump
+/// in a single transaction, other tables are left alone. Table definitions
+/// are kept as they are in the db, foreign keys from other tables still have
+/// to be satisfied by the restored rows. Returns the restored tables.
+pub fn restore_tables(input: RestoreTablesInput) -> ResultDynError<Vec<String>> {
+  let db_uri = input.db_uri;
+  let schema_sql = to_sql(ToSqlInput {
+    dump_path: input.dump_path,
+    format: input.format,
+    content: SqlContent::SchemaOnly,
+  })?;
+  let selection = TableSelection::select(&schema_sql, input.tables, input.schemas)?;
+  let mut command = DbConnectionConfig::from(db_uri)?.command("psql");
+
+  (input.before_changing_db)()?;
+
+  command
+    .arg("--set=ON_ERROR_STOP=1")
+    .arg("--single-transaction");
+
+  log::debug!("Restoring tables {:?}", selection.tables());
+
+  let (output, written) = run_with_stdin(command, &mut |writer| {
+    writer.write_all(selection.before_data_sql().as_bytes())?;
+
+    let mut selected_data_writer = SelectedDataWriter::new(&selection, writer);
+
+    write_sql(
+      ToSqlInput {
+        dump_path: input.dump_path,
+        format: input.format,
+        content: SqlContent::DataOnly,
+      },
+      &mut selected_data_writer,
+    )?;
+    selected_data_writer.flush()?;
+    writer.write_all(selection.after_data_sql().as_bytes())?;
+
+    return Ok(());
+  })?;
+
+  if !output.status.success() {
+    return Err(
+      DbError::restore_error(
+        db_uri,
+        DbRestoreStep::RestoreDb,
+        Some(stderr_message(&output)),
+      )
+      .into(),
+    );
+  }
+
+  written?;
+
+  return Ok(selection.tables().to_vec());
+}
+
+/// Drops and recreates the db, then restores the dump into it.
+fn restore_in_place(input: RestoreInput) -> ResultDynError<String> {
+  let db_uri = input.db_uri;
+
+  if let (DumpFormat::Directory, DumpSource::Stream(_)) = (input.format, &input.dump) {
+    return Err(DbError::DirectoryDumpStreamError.into());
+  }
+
+  let db_connection_config = DbConnectionConfig::from(db_uri)?;
 
   log::debug!("Parsed config {:?}", db_connection_config);
-  log::debug!("Writing dump to a temp file");
-  fs::write(temp_file_path, input.sql)?;
-
-  let password = db_connection_config
-    .password
-    .or({ Some("".into()) })
-    .unwrap();
-  let port = db_connection_config.port.or({ Some("".into()) }).unwrap();
-  let username = format!("--username={}", db_connection_config.username);
-  let dbname = format!("--dbname={}", db_connection_config.db_name);
-  let host = format!("--host={}", db_connection_config.host);
-  let port = format!("--port={}", port);
+
+  (input.before_changing_db)()?;
+
+  if input.terminate_connections {
+    log::debug!("Terminating connections");
+
+    if let Err(err) = terminate_connections(&db_connection_config) {
+      allow_connections(&db_connection_config)?;
+
+      return Err(
+        DbError::restore_error(
+          db_uri,
+          DbRestoreStep::TerminateConnections,
+          Some(err.to_string()),
+        )
+        .into(),
+      );
+    }
+  }
 
   // Drop DB
   log::debug!("Dropping DB");
 
-  let output = Command::new("dropdb")
-    .env("PGPASSWORD", &password)
-    .arg(&username)
-    .arg(&host)
-    .arg(&port)
+  let output = db_connection_config
+    .maintenance_command("dropdb")
+    .arg("--if-exists")
     .arg(&db_connection_config.db_name)
     .output()?;
 
-  log::debug!("Drop db result {:?}", output);
+  log::debug!("Drop db result {}", output.status);
 
   if !output.status.success() {
+    if input.terminate_connections {
+      allow_connections(&db_connection_config)?;
+    }
+
     return Err(
-      DbError::restore_error(
-        db_uri,
-        DbRestoreStep::DropDb,
-        Some(format!("output: {:?}", output)),
-      )
-      .into(),
+      DbError::restore_error(db_uri, DbRestoreStep::DropDb, Some(stderr_message(&output))).into(),
     );
   }
 
   // Create DB
   log::debug!("Recreating DB");
 
-  let output = Command::new("createdb")
-    .env("PGPASSWORD", &password)
-    .arg(&username)
-    .arg(&host)
-    .arg(&port)
+  let output = db_connection_config
+    .maintenance_command("createdb")
     .arg(&db_connection_config.db_name)
     .output()?;
 
-  log::debug!("Create db result {:?}", output);
+  log::debug!("Create db result {}", output.status);
 
   if !output.status.success() {
     return Err(
       DbError::restore_error(
         db_uri,
         DbRestoreStep::CreateDb,
-        Some(format!("output: {:?}", output)),
+        Some(stderr_message(&output)),
       )
       .into(),
     );
   }
 
   // Run pg_restore
-  log::debug!("Running pg_restore");
-  let mut command = Command::new("pg_restore");
+  let mut command = match input.format {
+    DumpFormat::Plain | DumpFormat::Tables => {
+      log::debug!("Running psql");
+      let mut command = db_connection_config.command("psql");
+      let file_paths = match (&input.dump, input.format) {
+        (DumpSource::Stream(_), _) => vec![],
+        (DumpSource::Path(dump_path), DumpFormat::Tables) => table_files::files(dump_path)?,
+        (DumpSource::Path(dump_path), _) => vec![PathBuf::from(dump_path)],
+      };
 
-  command
-    .env("PGPASSWORD", &password)
-    .arg(&username)
-    .arg(&dbname)
-    .arg(&host)
-    .arg(&port)
-    .arg("--single-transaction")
-    .arg(temp_file_path);
+      command.arg("--set=ON_ERROR_STOP=1");
+
+      for file_path in file_paths {
+        command.arg(format!("--file={}", file_path.display()));
+      }
+
+      command
+    }
+    _ => {
+      log::debug!("Running pg_restore");
+      let mut command = db_connection_config.command("pg_restore");
+
+      command.args(restore_option_args(input.dump_options));
+
+      if let DumpSource::Path(dump_path) = &input.dump {
+        command.arg(dump_path);
+      }
+
+      command
+    }
+  };
 
-  log::debug!("Created command {:?}", command);
+  command.arg("--single-transaction");
 
-  let output = command.output()?;
+  // The command debug output would include the PGPASSWORD env
+  log::debug!(
+    "Created command {:?} {:?}",
+    command.get_program(),
+    command.get_args().collect::<Vec<_>>()
+  );
+
+  let (output, written) = match input.dump {
+    DumpSource::Path(_) => (command.output()?, Ok(())),
+    DumpSource::Stream(write_dump) => run_with_stdin(command, write_dump)?,
+  };
 
   if !output.status.success() {
     return Err(
       DbError::restore_error(
         db_uri,
         DbRestoreStep::RestoreDb,
-        Some(format!("output: {:?}", output)),
+        Some(stderr_message(&output)),
       )
       .into(),
     );
   }
 
-  log::debug!("Cleaning out temp file");
-  fs::remove_file(temp_file_path)?;
+  written?;
+
+  if let Some(verify_checksum) = input.verify_checksum {
+    log::debug!("Verifying restored DB");
+
+    let restored_checksum = live_content_checksum(db_uri, input.dump_options)?;
+
+    if !restored_checksum.matches(verify_checksum) {
+      return Err(
+        DbError::restore_error(
+          db_uri,
+          DbRestoreStep::VerifyRestore,
+          Some(String::from("restored db content does not match the dump")),
+        )
+        .into(),
+      );
+    }
+  }
 
   let output = String::from_utf8(output.stdout)?;
 
   return Ok(output);
 }
 
+/// Content checksum of what is currently in the db, comparable with
+/// `DumpMetadata::content_checksum` because the plain script pg_dump writes
+/// is the same script pg_restore renders from a dump of an identical db.
+fn live_content_checksum(db_uri: &str, options: &DumpOptions) -> ResultDynError<ContentChecksum> {
+  let mut hasher = CanonicalSqlHasher::default();
+
+  write_live_sql(db_uri, options, &mut hasher)?;
+
+  return Ok(hasher.finish());
+}
+
+/// Content checksum of a plain sql script as written by `write_live_sql`.
+pub fn sql_content_checksum(sql: &[u8]) -> ResultDynError<String> {
+  let mut hasher = CanonicalSqlHasher::default();
+
+  hasher.write_all(sql)?;
+
+  return Ok(hasher.finish().checksum);
+}
+
+/// Writes the plain sql script of what is currently in the db,
+/// filtered like dumps of the db.
+pub fn write_live_sql(
+  db_uri: &str,
+  options: &DumpOptions,
+  writer: &mut dyn Write,
+) -> ResultDynError<()> {
+  let mut command = DbConnectionConfig::from(db_uri)?.command("pg_dump");
+
+  command.arg("--format=p").args(dump_option_args(options));
+
+  return run_with_stdout(command, writer);
+}
+
+/// Same as `write_live_sql`, returns the content checksum of the sql
+/// computed while it is written, see `sql_content_checksum`.
+pub fn write_live_sql_with_checksum(
+  db_uri: &str,
+  options: &DumpOptions,
+  writer: &mut dyn Write,
+) -> ResultDynError<ContentChecksum> {
+  let mut checksum_writer = ChecksumWriter {
+    hasher: CanonicalSqlHasher::default(),
+    writer,
+  };
+
+  write_live_sql(db_uri, options, &mut checksum_writer)?;
+  checksum_writer.writer.flush()?;
+
+  return Ok(checksum_writer.hasher.finish());
+}
+
+/// Hashes what goes through it into `writer`.
+struct ChecksumWriter<'a> {
+  hasher: CanonicalSqlHasher,
+  writer: &'a mut dyn Write,
+}
+
+impl<'a> Write for ChecksumWriter<'a> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.writer.write_all(buf)?;
+    self.hasher.write_all(buf)?;
+
+    return Ok(buf.len());
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    return self.writer.flush();
+  }
+}
+
+/// Checksum `restore` can verify the restored db against, `None` when the
+/// commit has no content checksum or the installed pg_dump has another major
+/// version than the one that made the dump since its output differs.
+pub fn restore_verification_checksum(metadata: &DumpMetadata) -> ResultDynError<Option<String>> {
+  let output = Command::new("pg_dump").arg("--version").output()?;
+  let output = String::from_utf8(output.stdout)?;
+
+  // Output looks like `pg_dump (PostgreSQL) 12.1`
+  let pg_dump_version = output.split_whitespace().nth(2).unwrap_or("");
+  let is_same_major_version = major_version(pg_dump_version).is_some()
+    && major_version(pg_dump_version) == major_version(&metadata.dump_tool_version);
+
+  if !is_same_major_version {
+    return Ok(None);
+  }
+
+  return Ok(metadata.content_checksum.clone());
+}
+
 #[cfg(test)]
 mod test {
   use super::*;
 
-  mod DbConnectionConfigTest {
+  mod canonical_sql_hasher {
     use super::*;
 
-    mod from {
-      use super::*;
+    fn hash(chunks: &[&str]) -> ContentChecksum {
+      let mut hasher = CanonicalSqlHasher::default();
+
+      for chunk in chunks {
+        hasher.write_all(chunk.as_bytes()).unwrap();
+      }
+
+      return hasher.finish();
+    }
 
-      #[test]
-      fn it_should_return_valid_db_config_without_password() -> ResultDynError<()> {
-        let config = DbConnectionConfig::from("yay@localhost/testdb")?;
+    #[test]
+    fn it_should_ignore_restrict_keys() {
+      let expected = DumpMetadata::checksum(b"CREATE TABLE a (\n);");
 
-        assert_eq!(config.username, "yay");
-        assert!(config.password.is_none());
-        assert_eq!(config.db_name, "testdb");
-        assert_eq!(config.host, "localhost");
-        assert!(config.port.is_none());
+      assert_eq!(
+        hash(&["\\restrict abc\nCREATE TABLE a (\n);\n\\unrestrict abc\n"]).checksum,
+        expected
+      );
+      assert_eq!(
+        hash(&[
+          "\\restrict xyz\nCREATE TA",
+          "BLE a (\n",
+          ");\n\\unrestrict xyz"
+        ])
+        .checksum,
+        expected
+      );
+    }
 
-        return Ok(());
-      }
+    #[test]
+    fn it_should_ignore_the_versions() {
+      let script = |server_version: &str, pg_dump_version: &str| {
+        return format!(
+          "--\n-- Dumped from database version {}\n-- Dumped by pg_dump version {}\n\nCREATE TABLE a (\n);\n",
+          server_version, pg_dump_version
+        );
+      };
+      let checksum = hash(&[&script("12.1", "12.1")]);
+      let upgraded_checksum = hash(&[&script("16.4 (Debian 16.4-1)", "16.6")]);
 
-      #[test]
-      fn it_should_return_valid_db_config_with_password() -> ResultDynError<()> {
-        let config = DbConnectionConfig::from("yay:hidden@localhost/testdb")?;
+      assert_eq!(checksum.checksum, upgraded_checksum.checksum);
+      assert_eq!(
+        checksum.legacy_checksum,
+        DumpMetadata::checksum(script("12.1", "12.1").trim_end().as_bytes())
+      );
+      assert!(checksum.matches(&checksum.legacy_checksum));
+      assert!(!upgraded_checksum.matches(&checksum.legacy_checksum));
+    }
+  }
 
-        assert_eq!(config.username, "yay");
-        assert_eq!(config.password.unwrap(), "hidden");
-        assert_eq!(config.db_name, "testdb");
-        assert_eq!(config.host, "localhost");
-        assert!(config.port.is_none());
+  mod dump_option_args {
+    use super::*;
 
-        return Ok(());
-      }
+    #[test]
+    fn it_should_pass_every_pattern_and_flag() {
+      let options = DumpOptions {
+        include_schemas: vec![String::from("public")],
+        exclude_schemas: vec![String::from("audit")],
+        include_tables: vec![String::from("users"), String::from("orders")],
+        exclude_tables: vec![String::from("public.sessions")],
+        exclude_table_data: vec![String::from("logs_*")],
+        no_owner: true,
+        no_acl: true,
+      };
 
-      #[test]
-      fn it_should_return_valid_db_config_with_complete_uri() -> ResultDynError<()> {
-        let config = DbConnectionConfig::from("yay:hidden@localhost:5544/testdb")?;
+      assert_eq!(
+        dump_option_args(&options),
+        vec![
+          "--schema=public",
+          "--exclude-schema=audit",
+          "--table=users",
+          "--table=orders",
+          "--exclude-table=public.sessions",
+          "--exclude-table-data=logs_*",
+          "--no-owner",
+          "--no-acl",
+        ]
+      );
+      assert!(dump_option_args(&DumpOptions::default()).is_empty());
+    }
+  }
 
-        assert_eq!(config.username, "yay");
-        assert_eq!(config.password.unwrap(), "hidden");
-        assert_eq!(config.db_name, "testdb");
-        assert_eq!(config.host, "localhost");
-        assert_eq!(config.port.unwrap(), "5544");
+  mod major_version {
+    use super::*;
 
-        return Ok(());
-      }
+    #[test]
+    fn it_should_parse_major_versions() {
+      assert_eq!(major_version("9.6.3"), Some((9, 6)));
+      assert_eq!(
+        major_version("12.1 (Debian 12.1-1.pgdg100+1)"),
+        Some((12, 0))
+      );
+      assert_eq!(major_version(""), None);
+    }
+  }
+
+  mod restore_compatibility_warning {
+    use super::*;
+
+    #[test]
+    fn it_should_not_warn_for_dumps_restored_with_psql() -> ResultDynError<()> {
+      let metadata = |format: DumpFormat| DumpMetadata {
+        server_version: String::from("99.0"),
+        dump_tool_version: String::from("99.0"),
+        host: String::from("localhost:5432"),
+        db_name: String::from("testdb"),
+        format: String::from(format.as_str()),
+        size: 0,
+        checksum: String::new(),
+        content_checksum: None,
+        dump_options: None,
+      };
+
+      assert_eq!(
+        restore_compatibility_warning(&metadata(DumpFormat::Plain))?,
+        None
+      );
+      assert_eq!(
+        restore_compatibility_warning(&metadata(DumpFormat::Tables))?,
+        None
+      );
+      assert!(restore_compatibility_warning(&DumpMetadata {
+        format: String::from("zip"),
+        ..metadata(DumpFormat::Plain)
+      })
+      .is_err());
+
+      return Ok(());
+    }
+  }
+
+  mod db_uri_with_db_name {
+    use super::*;
+
+    #[test]
+    fn it_should_only_replace_the_db_name() -> ResultDynError<()> {
+      assert_eq!(
+        db_uri_with_db_name("yay:hidden@localhost:5544/testdb", "testdb_old")?,
+        "yay:hidden@localhost:5544/testdb_old"
+      );
+      assert_eq!(
+        db_uri_with_db_name("yay@localhost", "testdb_old")?,
+        "yay@localhost/testdb_old"
+      );
+      assert!(db_uri_with_db_name("yay@localhost:port/testdb", "testdb_old").is_err());
+      assert_eq!(
+        db_uri_with_db_name(
+          "postgresql://yay@db1,db2:5544/testdb?sslmode=require",
+          "test db"
+        )?,
+        "postgresql://yay@db1,db2:5544/test%20db?sslmode=require"
+      );
+      assert_eq!(
+        db_uri_with_db_name("host=localhost dbname=testdb", "testdb_old")?,
+        "host='localhost' dbname='testdb_old'"
+      );
+
+      return Ok(());
+    }
+  }
+
+  mod restore {
+    use super::*;
+    use std::env;
+
+    /// Tables referencing each other, neither foreign key cascades deletes.
+    const MUTUALLY_REFERENCING_SQL: &str = "
+      CREATE TABLE authors (id integer PRIMARY KEY, favorite_book_id integer);
+      CREATE TABLE books (id integer PRIMARY KEY, author_id integer NOT NULL REFERENCES authors (id));
+      ALTER TABLE authors ADD FOREIGN KEY (favorite_book_id) REFERENCES books (id);
+      INSERT INTO authors VALUES (1, NULL);
+      INSERT INTO books VALUES (10, 1);
+      UPDATE authors SET favorite_book_id = 10;
+    ";
+
+    /// Needs a postgres server, run it with
+    /// `JAB_TEST_DB_URI=user:secret@localhost/postgres cargo test -- --ignored`,
+    /// the jab_test_foreign_keys db is dropped and recreated on that server.
+    #[test]
+    #[ignore]
+    fn it_should_restore_foreign_keys_without_on_delete_cascade() -> ResultDynError<()> {
+      let db_uri = db_uri_with_db_name(&env::var("JAB_TEST_DB_URI")?, "jab_test_foreign_keys")?;
+      let dump_path = env::temp_dir().join("jab-test-foreign-keys.dump");
+      let dump_options = DumpOptions::default();
+      let mut write_sql = |writer: &mut dyn Write| {
+        return Ok(writer.write_all(MUTUALLY_REFERENCING_SQL.as_bytes())?);
+      };
+
+      restore(RestoreInput {
+        db_uri: &db_uri,
+        format: DumpFormat::Plain,
+        dump: DumpSource::Stream(&mut write_sql),
+        verify_checksum: None,
+        terminate_connections: false,
+        swap: false,
+        dump_options: &dump_options,
+        before_changing_db: &mut || Ok(()),
+      })?;
+      dump(DumpInput {
+        db_uri: &db_uri,
+        format: DumpFormat::Custom,
+        path: &dump_path,
+        options: &dump_options,
+      })?;
+
+      let metadata = dump_metadata(DumpMetadataInput {
+        db_uri: &db_uri,
+        format: DumpFormat::Custom,
+        dump_path: &dump_path,
+        options: &dump_options,
+      })?;
+
+      let mut before_changing_db_count = 0;
+
+      // Restoring over the db holding the rows, then checking it against the dump
+      restore(RestoreInput {
+        db_uri: &db_uri,
+        format: DumpFormat::Custom,
+        dump: DumpSource::Path(&dump_path),
+        verify_checksum: metadata.content_checksum.as_deref(),
+        terminate_connections: false,
+        swap: false,
+        dump_options: &dump_options,
+        before_changing_db: &mut || {
+          before_changing_db_count += 1;
+
+          return Ok(());
+        },
+      })?;
+
+      assert_eq!(before_changing_db_count, 1);
+
+      DbConnectionConfig::from(&db_uri)?.drop_db()?;
+      fs::remove_file(&dump_path)?;
+
+      return Ok(());
     }
   }
 }
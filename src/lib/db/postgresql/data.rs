@@ -0,0 +1,600 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::db::postgresql::schema::Schema;
+
+/// A row as column name to its raw `COPY` text value, `\N` is null.
+pub type Row = BTreeMap<String, String>;
+
+#[derive(Debug, Default)]
+pub struct TableData {
+  pub columns: Vec<String>,
+  pub rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Data {
+  tables: BTreeMap<String, TableData>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TableDataDiff {
+  pub table: String,
+  /// Columns used to match rows, the whole row when the table has no primary key.
+  pub key_columns: Vec<String>,
+  pub inserted: Vec<Row>,
+  pub deleted: Vec<Row>,
+  /// Pairs of the row before and after the update.
+  pub updated: Vec<(Row, Row)>,
+}
+
+impl TableDataDiff {
+  pub fn is_empty(&self) -> bool {
+    return self.inserted.is_empty() && self.deleted.is_empty() && self.updated.is_empty();
+  }
+
+  pub fn key_of(&self, row: &Row) -> String {
+    return self
+      .key_columns
+      .iter()
+      .map(|column| {
+        format!(
+          "{}={}",
+          column,
+          display_value(row.get(column).map_or("", String::as_ref))
+        )
+      })
+      .collect::<Vec<String>>()
+      .join(" ");
+  }
+}
+
+/// Decodes a raw `COPY` text value for display, null is shown as `NULL`
+/// and control characters stay escaped so a row fits on one line.
+pub fn display_value(value: &str) -> String {
+  if value == "\\N" {
+    return String::from("NULL");
+  }
+
+  let mut decoded: Vec<u8> = vec![];
+  let mut bytes = value.bytes().peekable();
+
+  while let Some(byte) = bytes.next() {
+    if byte != b'\\' {
+      decoded.push(byte);
+      continue;
+    }
+
+    let escaped = match bytes.next() {
+      None => b'\\',
+      Some(b'b') => 0x08,
+      Some(b'f') => 0x0c,
+      Some(b'n') => b'\n',
+      Some(b'r') => b'\r',
+      Some(b't') => b'\t',
+      Some(b'v') => 0x0b,
+      Some(b'x') => parse_escaped_byte(&mut bytes, 16, 2, b'x'),
+      Some(digit @ b'0'..=b'7') => {
+        let mut code = vec![digit];
+        code.extend(take_digits(&mut bytes, 8, 2));
+
+        u8::from_str_radix(std::str::from_utf8(&code).unwrap(), 8).unwrap_or(digit)
+      }
+      Some(other) => other,
+    };
+
+    decoded.push(escaped);
+  }
+
+  let decoded = String::from_utf8_lossy(&decoded);
+
+  return decoded
+    .chars()
+    .map(|char| match char.is_control() {
+      true => char.escape_default().collect(),
+      false => char.to_string(),
+    })
+    .collect();
+}
+
+fn parse_escaped_byte(
+  bytes: &mut std::iter::Peekable<std::str::Bytes>,
+  radix: u32,
+  max_digits: usize,
+  fallback: u8,
+) -> u8 {
+  let digits = take_digits(bytes, radix, max_digits);
+
+  return match digits.is_empty() {
+    true => fallback,
+    false => u8::from_str_radix(std::str::from_utf8(&digits).unwrap(), radix).unwrap_or(fallback),
+  };
+}
+
+fn take_digits(
+  bytes: &mut std::iter::Peekable<std::str::Bytes>,
+  radix: u32,
+  max_digits: usize,
+) -> Vec<u8> {
+  let mut digits = vec![];
+
+  while digits.len() < max_digits {
+    match bytes.peek() {
+      Some(byte) if (*byte as char).is_digit(radix) => digits.push(bytes.next().unwrap()),
+      _ => break,
+    }
+  }
+
+  return digits;
+}
+
+/// Row count of a table with a checksum of its rows, the checksum does
+/// not depend on the row order which changes when rows are updated.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TableSummary {
+  pub row_count: usize,
+  checksum: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TableSummaryDiff {
+  pub table: String,
+  pub from_row_count: usize,
+  pub to_row_count: usize,
+}
+
+impl TableSummary {
+  /// Tables whose rows differ, a missing table has no rows.
+  pub fn diff(
+    from: &BTreeMap<String, TableSummary>,
+    to: &BTreeMap<String, TableSummary>,
+  ) -> Vec<TableSummaryDiff> {
+    let empty_summary = TableSummary::default();
+    let mut table_names: Vec<&String> = from.keys().chain(to.keys()).collect();
+    table_names.sort();
+    table_names.dedup();
+
+    return table_names
+      .into_iter()
+      .filter_map(|table_name| {
+        let from_summary = from.get(table_name).unwrap_or(&empty_summary);
+        let to_summary = to.get(table_name).unwrap_or(&empty_summary);
+
+        if from_summary == to_summary {
+          return None;
+        }
+
+        return Some(TableSummaryDiff {
+          table: table_name.clone(),
+          from_row_count: from_summary.row_count,
+          to_row_count: to_summary.row_count,
+        });
+      })
+      .collect();
+  }
+
+  fn add_row(&mut self, row: &[u8]) {
+    let mut row_checksum = [0; 8];
+    row_checksum.copy_from_slice(&Sha256::digest(row)[..8]);
+
+    self.row_count += 1;
+    self.checksum = self.checksum.wrapping_add(u64::from_le_bytes(row_checksum));
+  }
+}
+
+/// Reads a sql script as it is written into it, e.g. streamed from pg_dump,
+/// so the script is never held in memory as a whole. The schema part of
+/// the script is kept for `Schema::parse` and tables are summarized,
+/// their rows are only kept with `keep_rows`.
+pub struct ScriptReader {
+  keep_rows: bool,
+  line: Vec<u8>,
+  schema_sql: String,
+  summaries: BTreeMap<String, TableSummary>,
+  data: Data,
+  current_table: Option<(String, TableData)>,
+}
+
+impl ScriptReader {
+  pub fn new(keep_rows: bool) -> ScriptReader {
+    return ScriptReader {
+      keep_rows,
+      line: vec![],
+      schema_sql: String::new(),
+      summaries: BTreeMap::new(),
+      data: Data::default(),
+      current_table: None,
+    };
+  }
+
+  pub fn schema_sql(&self) -> &str {
+    return &self.schema_sql;
+  }
+
+  pub fn summaries(&self) -> &BTreeMap<String, TableSummary> {
+    return &self.summaries;
+  }
+
+  /// Rows of every table, empty without `keep_rows`.
+  pub fn data(&self) -> &Data {
+    return &self.data;
+  }
+
+  fn read_line(&mut self) {
+    let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+
+    if let Some((table_name, mut table)) = self.current_table.take() {
+      if line == b"\\." {
+        if self.keep_rows {
+          self.data.tables.insert(table_name, table);
+        }
+      } else {
+        let line = String::from_utf8_lossy(line);
+
+        self
+          .summaries
+          .entry(table_name.clone())
+          .or_default()
+          .add_row(line.as_bytes());
+
+        if self.keep_rows {
+          table
+            .rows
+            .push(line.split('\t').map(String::from).collect());
+        }
+
+        self.current_table = Some((table_name, table));
+      }
+    } else {
+      let line = String::from_utf8_lossy(line);
+
+      match line.strip_prefix("COPY ") {
+        Some(copy) => {
+          let (table_name, table) = parse_copy(copy);
+
+          self.summaries.entry(table_name.clone()).or_default();
+          self.current_table = Some((table_name, table));
+        }
+        None => {
+          self.schema_sql.push_str(&line);
+          self.schema_sql.push('\n');
+        }
+      }
+    }
+
+    self.line.clear();
+  }
+}
+
+impl Write for ScriptReader {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    for chunk in buf.split_inclusive(|byte| *byte == b'\n') {
+      match chunk.strip_suffix(b"\n") {
+        Some(line) => {
+          self.line.extend_from_slice(line);
+          self.read_line();
+        }
+        None => self.line.extend_from_slice(chunk),
+      }
+    }
+
+    return Ok(buf.len());
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    if !self.line.is_empty() {
+      self.read_line();
+    }
+
+    return Ok(());
+  }
+}
+
+/// `public.users (id, name) FROM stdin;` into the table name and its columns.
+fn parse_copy(copy: &str) -> (String, TableData) {
+  let columns_start = copy.find(" (").unwrap_or(copy.len());
+  let columns_end = copy.rfind(") FROM stdin;").unwrap_or(copy.len());
+  let columns = copy
+    .get(columns_start + 2..columns_end)
+    .unwrap_or("")
+    .split(", ")
+    .map(String::from)
+    .collect();
+
+  return (
+    String::from(&copy[..columns_start]),
+    TableData {
+      columns,
+      rows: vec![],
+    },
+  );
+}
+
+impl Data {
+  /// Parses the `COPY ... FROM stdin;` blocks produced by `pg_restore --data-only`.
+  pub fn parse(sql: &str) -> Data {
+    let mut data = Data::default();
+    let mut current_table: Option<(String, TableData)> = None;
+
+    for line in sql.lines() {
+      if let Some((table_name, mut table)) = current_table.take() {
+        if line == "\\." {
+          data.tables.insert(table_name, table);
+        } else {
+          table
+            .rows
+            .push(line.split('\t').map(String::from).collect());
+          current_table = Some((table_name, table));
+        }
+
+        continue;
+      }
+
+      if let Some(copy) = line.strip_prefix("COPY ") {
+        current_table = Some(parse_copy(copy));
+      }
+    }
+
+    return data;
+  }
+
+  /// Compares table rows between two snapshots, rows are matched by the
+  /// primary key found in `schema`. Only columns present in both snapshots
+  /// are compared so adding a column does not mark every row as updated.
+  pub fn diff(from: &Data, to: &Data, schema: &Schema) -> Vec<TableDataDiff> {
+    let empty_table = TableData::default();
+    let mut table_names: Vec<&String> = from.tables.keys().chain(to.tables.keys()).collect();
+    table_names.sort();
+    table_names.dedup();
+
+    return table_names
+      .into_iter()
+      .map(|table_name| {
+        let from_table = from.tables.get(table_name).unwrap_or(&empty_table);
+        let to_table = to.tables.get(table_name).unwrap_or(&empty_table);
+
+        return Data::diff_table(table_name, from_table, to_table, schema);
+      })
+      .filter(|diff| !diff.is_empty())
+      .collect();
+  }
+
+  fn diff_table(
+    table_name: &str,
+    from_table: &TableData,
+    to_table: &TableData,
+    schema: &Schema,
+  ) -> TableDataDiff {
+    let common_columns: Vec<String> = to_table
+      .columns
+      .iter()
+      .filter(|column| from_table.columns.contains(column))
+      .cloned()
+      .collect();
+
+    let key_columns = schema
+      .primary_key(table_name)
+      .filter(|key| key.iter().all(|column| common_columns.contains(column)))
+      .unwrap_or_else(|| common_columns.clone());
+
+    let from_rows = keyed_rows(from_table, &key_columns);
+    let mut to_rows = keyed_rows(to_table, &key_columns);
+    let mut diff = TableDataDiff {
+      table: String::from(table_name),
+      key_columns,
+      ..TableDataDiff::default()
+    };
+
+    for (key, from_row) in from_rows.into_iter() {
+      match to_rows.remove(&key) {
+        None => diff.deleted.push(from_row),
+        Some(to_row) => {
+          let is_updated = common_columns
+            .iter()
+            .any(|column| from_row.get(column) != to_row.get(column));
+
+          if is_updated {
+            diff.updated.push((from_row, to_row));
+          }
+        }
+      }
+    }
+
+    diff.inserted = to_rows.into_values().collect();
+
+    return diff;
+  }
+}
+
+/// Rows keyed by their key column values, duplicated keys (only possible
+/// without a primary key) get an occurrence suffix so they pair up in order.
+fn keyed_rows(table: &TableData, key_columns: &[String]) -> BTreeMap<Vec<String>, Row> {
+  let mut keyed_rows = BTreeMap::new();
+
+  for values in table.rows.iter() {
+    let row: Row = table
+      .columns
+      .iter()
+      .cloned()
+      .zip(values.iter().cloned())
+      .collect();
+
+    let mut key: Vec<String> = key_columns
+      .iter()
+      .map(|column| row.get(column).cloned().unwrap_or_default())
+      .collect();
+    key.push(String::from("0"));
+
+    let mut occurrence = 0;
+
+    while keyed_rows.contains_key(&key) {
+      occurrence += 1;
+      *key.last_mut().unwrap() = occurrence.to_string();
+    }
+
+    keyed_rows.insert(key, row);
+  }
+
+  return keyed_rows;
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const SCHEMA_SQL: &str = r#"
+--
+-- Name: users users_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.users
+    ADD CONSTRAINT users_pkey PRIMARY KEY (id);
+"#;
+
+  const FROM_SQL: &str = "
+COPY public.users (id, name) FROM stdin;
+1\ta
+2\tb
+3\tc
+\\.
+
+COPY public.logs (message) FROM stdin;
+hello
+hello
+\\.
+";
+
+  const TO_SQL: &str = "
+COPY public.users (id, name, email) FROM stdin;
+1\ta\t\\N
+2\tbb\t\\N
+4\td\t\\N
+\\.
+
+COPY public.logs (message) FROM stdin;
+hello
+\\.
+";
+
+  mod display_value {
+    use super::*;
+
+    #[test]
+    fn it_should_show_null() {
+      assert_eq!(display_value("\\N"), "NULL");
+      assert_eq!(display_value("N"), "N");
+    }
+
+    #[test]
+    fn it_should_decode_escapes() {
+      assert_eq!(display_value("a\\\\b"), "a\\b");
+      assert_eq!(display_value("caf\\303\\251"), "café");
+      assert_eq!(display_value("\\x41\\101"), "AA");
+      assert_eq!(display_value("a\\tb\\nc"), "a\\tb\\nc");
+    }
+  }
+
+  mod parse {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_copy_blocks() {
+      let data = Data::parse(TO_SQL);
+      let users = data.tables.get("public.users").unwrap();
+
+      assert_eq!(users.columns, vec!["id", "name", "email"]);
+      assert_eq!(users.rows.len(), 3);
+      assert_eq!(users.rows[1], vec!["2", "bb", "\\N"]);
+    }
+  }
+
+  mod script_reader {
+    use super::*;
+
+    #[test]
+    fn it_should_summarize_tables_whatever_the_row_order() -> io::Result<()> {
+      let read = |sql: &str, keep_rows: bool| -> io::Result<ScriptReader> {
+        let mut reader = ScriptReader::new(keep_rows);
+
+        // Lines split over writes like a streamed dump
+        for chunk in sql.as_bytes().chunks(7) {
+          reader.write_all(chunk)?;
+        }
+
+        reader.flush()?;
+
+        return Ok(reader);
+      };
+      let from = read(&format!("{}{}", SCHEMA_SQL, FROM_SQL), false)?;
+      let reordered = read(
+        "COPY public.logs (message) FROM stdin;\nhello\nhello\n\\.\n\
+         COPY public.users (id, name) FROM stdin;\n3\tc\n1\ta\n2\tb\n\\.\n",
+        false,
+      )?;
+      let to = read(TO_SQL, true)?;
+
+      assert_eq!(from.schema_sql().trim(), SCHEMA_SQL.trim());
+      assert!(from.data().tables.is_empty());
+      assert_eq!(from.summaries()["public.users"].row_count, 3);
+      assert!(TableSummary::diff(from.summaries(), reordered.summaries()).is_empty());
+      assert_eq!(
+        TableSummary::diff(from.summaries(), to.summaries()),
+        vec![
+          TableSummaryDiff {
+            table: String::from("public.logs"),
+            from_row_count: 2,
+            to_row_count: 1,
+          },
+          TableSummaryDiff {
+            table: String::from("public.users"),
+            from_row_count: 3,
+            to_row_count: 3,
+          },
+        ]
+      );
+      assert_eq!(to.data().tables["public.users"].rows.len(), 3);
+
+      return Ok(());
+    }
+  }
+
+  mod diff {
+    use super::*;
+
+    #[test]
+    fn it_should_match_rows_by_primary_key() {
+      let schema = Schema::parse(SCHEMA_SQL);
+      let diffs = Data::diff(&Data::parse(FROM_SQL), &Data::parse(TO_SQL), &schema);
+      let users = diffs
+        .iter()
+        .find(|diff| diff.table == "public.users")
+        .unwrap();
+
+      assert_eq!(users.key_columns, vec!["id"]);
+      assert_eq!(users.inserted.len(), 1);
+      assert_eq!(users.key_of(&users.inserted[0]), "id=4");
+      assert_eq!(users.deleted.len(), 1);
+      assert_eq!(users.key_of(&users.deleted[0]), "id=3");
+      assert_eq!(users.updated.len(), 1);
+      assert_eq!(users.updated[0].1.get("name").unwrap(), "bb");
+    }
+
+    #[test]
+    fn it_should_match_whole_rows_without_primary_key() {
+      let schema = Schema::parse(SCHEMA_SQL);
+      let diffs = Data::diff(&Data::parse(FROM_SQL), &Data::parse(TO_SQL), &schema);
+      let logs = diffs
+        .iter()
+        .find(|diff| diff.table == "public.logs")
+        .unwrap();
+
+      assert!(logs.inserted.is_empty());
+      assert_eq!(logs.deleted.len(), 1);
+      assert!(logs.updated.is_empty());
+    }
+  }
+}
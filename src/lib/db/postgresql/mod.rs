@@ -1 +1,6 @@
 pub mod client;
+pub mod connection;
+pub mod data;
+pub mod schema;
+pub mod table_files;
+pub mod table_selection;
@@ -0,0 +1,454 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub enum SchemaObjectKind {
+  Table,
+  Column,
+  Index,
+  Constraint,
+  Function,
+  Sequence,
+}
+
+impl fmt::Display for SchemaObjectKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let label = match self {
+      SchemaObjectKind::Table => "Tables",
+      SchemaObjectKind::Column => "Columns",
+      SchemaObjectKind::Index => "Indexes",
+      SchemaObjectKind::Constraint => "Constraints",
+      SchemaObjectKind::Function => "Functions",
+      SchemaObjectKind::Sequence => "Sequences",
+    };
+
+    return write!(f, "{}", label);
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaChange {
+  Added { definition: String },
+  Removed { definition: String },
+  Changed { from: String, to: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaObjectDiff {
+  pub kind: SchemaObjectKind,
+  /// Qualified name, e.g. `public.users` or `public.users.email` for columns.
+  pub name: String,
+  pub change: SchemaChange,
+}
+
+/// Schema objects keyed by kind and qualified name, the value is
+/// the object definition that we use for comparison.
+#[derive(Debug, Default)]
+pub struct Schema {
+  objects: BTreeMap<(SchemaObjectKind, String), String>,
+}
+
+struct TocEntry {
+  name: String,
+  object_type: String,
+  schema: String,
+  body: Vec<String>,
+}
+
+impl TocEntry {
+  fn qualified_name(&self) -> String {
+    if self.schema == "-" {
+      return self.name.clone();
+    }
+
+    return format!("{}.{}", self.schema, self.name);
+  }
+
+  /// Entries such as constraints and defaults are named `<table> <object>`.
+  fn qualified_table_member_name(&self) -> String {
+    let name = self.name.replacen(' ', ".", 1);
+
+    if self.schema == "-" {
+      return name;
+    }
+
+    return format!("{}.{}", self.schema, name);
+  }
+}
+
+impl Schema {
+  /// Parses the plain sql script produced by `pg_restore --schema-only`,
+  /// pg_restore prefixes every object with a header like
+  /// `-- Name: users; Type: TABLE; Schema: public; Owner: postgres`.
+  /// Table data sections of a full script are skipped.
+  pub fn parse(sql: &str) -> Schema {
+    let mut schema = Schema::default();
+
+    for entry in Schema::toc_entries(sql) {
+      match entry.object_type.as_ref() {
+        "TABLE" => schema.add_table(&entry),
+        "INDEX" => schema.add(SchemaObjectKind::Index, entry.qualified_name(), &entry.body),
+        "CONSTRAINT" | "FK CONSTRAINT" | "CHECK CONSTRAINT" => schema.add(
+          SchemaObjectKind::Constraint,
+          entry.qualified_table_member_name(),
+          &entry.body,
+        ),
+        "FUNCTION" | "PROCEDURE" => schema.add(
+          SchemaObjectKind::Function,
+          entry.qualified_name(),
+          &entry.body,
+        ),
+        "SEQUENCE" => schema.add(
+          SchemaObjectKind::Sequence,
+          entry.qualified_name(),
+          &entry.body,
+        ),
+        "DEFAULT" => schema.add_column_default(&entry),
+        _ => {}
+      }
+    }
+
+    return schema;
+  }
+
+  pub fn diff(from: &Schema, to: &Schema) -> Vec<SchemaObjectDiff> {
+    let mut diffs = vec![];
+
+    for ((kind, name), definition) in from.objects.iter() {
+      match to.objects.get(&(*kind, name.clone())) {
+        None => diffs.push(SchemaObjectDiff {
+          kind: *kind,
+          name: name.clone(),
+          change: SchemaChange::Removed {
+            definition: definition.clone(),
+          },
+        }),
+        Some(to_definition) if to_definition != definition => diffs.push(SchemaObjectDiff {
+          kind: *kind,
+          name: name.clone(),
+          change: SchemaChange::Changed {
+            from: definition.clone(),
+            to: to_definition.clone(),
+          },
+        }),
+        _ => {}
+      }
+    }
+
+    for ((kind, name), definition) in to.objects.iter() {
+      if !from.objects.contains_key(&(*kind, name.clone())) {
+        diffs.push(SchemaObjectDiff {
+          kind: *kind,
+          name: name.clone(),
+          change: SchemaChange::Added {
+            definition: definition.clone(),
+          },
+        });
+      }
+    }
+
+    diffs.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+
+    return diffs;
+  }
+
+  /// Primary key columns of the given qualified table name, e.g. `public.users`.
+  pub fn primary_key(&self, table: &str) -> Option<Vec<String>> {
+    let constraint_prefix = format!("{}.", table);
+
+    return self
+      .objects
+      .iter()
+      .filter(|((kind, name), _)| {
+        *kind == SchemaObjectKind::Constraint && name.starts_with(&constraint_prefix)
+      })
+      .filter_map(|(_, definition)| {
+        let columns = &definition[definition.find("PRIMARY KEY (")? + "PRIMARY KEY (".len()..];
+        let columns = &columns[..columns.find(')')?];
+
+        return Some(columns.split(", ").map(String::from).collect());
+      })
+      .next();
+  }
+
+  fn toc_entries(sql: &str) -> Vec<TocEntry> {
+    let mut entries: Vec<TocEntry> = vec![];
+
+    for line in sql.lines() {
+      let header = line
+        .strip_prefix("-- Name: ")
+        .or_else(|| line.strip_prefix("-- Data for Name: "));
+
+      if let Some(header) = header {
+        let mut parts = header.split("; ");
+        let name = parts.next().unwrap_or("");
+        let mut object_type = "";
+        let mut schema = "-";
+
+        for part in parts {
+          if let Some(value) = part.strip_prefix("Type: ") {
+            object_type = value;
+          } else if let Some(value) = part.strip_prefix("Schema: ") {
+            schema = value;
+          }
+        }
+
+        entries.push(TocEntry {
+          name: String::from(name),
+          object_type: String::from(object_type),
+          schema: String::from(schema),
+          body: vec![],
+        });
+
+        continue;
+      }
+
+      let is_noise = line.trim().is_empty()
+        || line.starts_with("--")
+        || line.starts_with("SET ")
+        || line.starts_with("SELECT pg_catalog.set_config")
+        || line.starts_with('\\');
+
+      if is_noise {
+        continue;
+      }
+
+      if let Some(entry) = entries.last_mut() {
+        entry.body.push(String::from(line));
+      }
+    }
+
+    return entries;
+  }
+
+  fn add(&mut self, kind: SchemaObjectKind, name: String, body: &[String]) {
+    self.objects.insert((kind, name), body.join("\n"));
+  }
+
+  /// Columns are tracked as their own objects so a new column shows up
+  /// as an added column instead of a changed table.
+  fn add_table(&mut self, entry: &TocEntry) {
+    let table_name = entry.qualified_name();
+    let mut table_body: Vec<String> = vec![];
+    let mut in_column_list = false;
+
+    for line in entry.body.iter() {
+      if !in_column_list {
+        table_body.push(line.clone());
+        in_column_list = line.starts_with("CREATE") && line.ends_with('(');
+
+        continue;
+      }
+
+      if line.starts_with(')') {
+        table_body.push(line.clone());
+        in_column_list = false;
+
+        continue;
+      }
+
+      let definition = line.trim().trim_end_matches(',');
+
+      if let Some(constraint) = definition.strip_prefix("CONSTRAINT ") {
+        let (name, definition) = split_identifier(constraint);
+        self.objects.insert(
+          (
+            SchemaObjectKind::Constraint,
+            format!("{}.{}", table_name, name),
+          ),
+          String::from(definition),
+        );
+      } else {
+        let (name, definition) = split_identifier(definition);
+        self.objects.insert(
+          (SchemaObjectKind::Column, format!("{}.{}", table_name, name)),
+          String::from(definition),
+        );
+      }
+    }
+
+    self.add(SchemaObjectKind::Table, table_name, &table_body);
+  }
+
+  fn add_column_default(&mut self, entry: &TocEntry) {
+    let column_key = (
+      SchemaObjectKind::Column,
+      entry.qualified_table_member_name(),
+    );
+    let default = entry
+      .body
+      .iter()
+      .filter_map(|line| line.find(" SET DEFAULT ").map(|index| &line[index + 5..]))
+      .next();
+
+    if let (Some(definition), Some(default)) = (self.objects.get_mut(&column_key), default) {
+      definition.push(' ');
+      definition.push_str(default.trim_end_matches(';'));
+    }
+  }
+}
+
+/// Splits `name rest of definition`, the name can be a quoted identifier.
+fn split_identifier(definition: &str) -> (&str, &str) {
+  let end = match definition.strip_prefix('"') {
+    Some(quoted) => quoted.find('"').map(|index| index + 2),
+    None => definition.find(' '),
+  }
+  .unwrap_or(definition.len());
+
+  return (&definition[..end], definition[end..].trim());
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const USERS_SQL: &str = r#"
+--
+-- Name: users; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.users (
+    id integer NOT NULL,
+    name text,
+    CONSTRAINT name_not_empty CHECK ((name <> ''::text))
+);
+
+
+ALTER TABLE public.users OWNER TO postgres;
+
+--
+-- Name: users_id_seq; Type: SEQUENCE; Schema: public; Owner: postgres
+--
+
+CREATE SEQUENCE public.users_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1;
+
+--
+-- Name: users id; Type: DEFAULT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.users ALTER COLUMN id SET DEFAULT nextval('public.users_id_seq'::regclass);
+
+--
+-- Name: users users_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.users
+    ADD CONSTRAINT users_pkey PRIMARY KEY (id);
+"#;
+
+  mod parse {
+    use super::*;
+
+    #[test]
+    fn it_should_split_tables_into_columns_and_constraints() {
+      let schema = Schema::parse(USERS_SQL);
+      let column = |name: &str| {
+        schema
+          .objects
+          .get(&(SchemaObjectKind::Column, String::from(name)))
+          .cloned()
+      };
+
+      assert_eq!(
+        column("public.users.id").unwrap(),
+        "integer NOT NULL DEFAULT nextval('public.users_id_seq'::regclass)"
+      );
+      assert_eq!(column("public.users.name").unwrap(), "text");
+      assert!(schema.objects.contains_key(&(
+        SchemaObjectKind::Constraint,
+        String::from("public.users.name_not_empty")
+      )));
+      assert!(schema.objects.contains_key(&(
+        SchemaObjectKind::Constraint,
+        String::from("public.users.users_pkey")
+      )));
+      assert!(schema.objects.contains_key(&(
+        SchemaObjectKind::Sequence,
+        String::from("public.users_id_seq")
+      )));
+    }
+
+    #[test]
+    fn it_should_skip_table_data() {
+      let sql = format!(
+        "{}\n--\n-- Data for Name: users; Type: TABLE DATA; Schema: public; Owner: postgres\n--\n\nCOPY public.users (id, name) FROM stdin;\n1\ta\n\\.\n",
+        USERS_SQL
+      );
+      let schema = Schema::parse(&sql);
+
+      assert_eq!(
+        schema.objects.get(&(
+          SchemaObjectKind::Constraint,
+          String::from("public.users.users_pkey")
+        )),
+        Schema::parse(USERS_SQL).objects.get(&(
+          SchemaObjectKind::Constraint,
+          String::from("public.users.users_pkey")
+        ))
+      );
+    }
+  }
+
+  mod primary_key {
+    use super::*;
+
+    #[test]
+    fn it_should_return_primary_key_columns() {
+      let schema = Schema::parse(USERS_SQL);
+
+      assert_eq!(
+        schema.primary_key("public.users").unwrap(),
+        vec![String::from("id")]
+      );
+      assert!(schema.primary_key("public.orders").is_none());
+    }
+  }
+
+  mod diff {
+    use super::*;
+
+    #[test]
+    fn it_should_return_nothing_for_identical_schemas() {
+      let diffs = Schema::diff(&Schema::parse(USERS_SQL), &Schema::parse(USERS_SQL));
+
+      assert!(diffs.is_empty());
+    }
+
+    #[test]
+    fn it_should_report_added_removed_and_changed_objects() {
+      let to_sql = USERS_SQL.replace(
+        "    name text,",
+        "    name character varying(100),\n    email text,",
+      );
+      let to_sql = to_sql[..to_sql.find("-- Name: users_id_seq").unwrap()].to_string();
+
+      let diffs = Schema::diff(&Schema::parse(USERS_SQL), &Schema::parse(&to_sql));
+      let names: Vec<(SchemaObjectKind, &str)> = diffs
+        .iter()
+        .map(|diff| (diff.kind, diff.name.as_ref()))
+        .collect();
+
+      assert_eq!(
+        names,
+        vec![
+          (SchemaObjectKind::Column, "public.users.email"),
+          (SchemaObjectKind::Column, "public.users.id"),
+          (SchemaObjectKind::Column, "public.users.name"),
+          (SchemaObjectKind::Constraint, "public.users.users_pkey"),
+          (SchemaObjectKind::Sequence, "public.users_id_seq"),
+        ]
+      );
+      assert_eq!(
+        diffs[2].change,
+        SchemaChange::Changed {
+          from: String::from("text"),
+          to: String::from("character varying(100)"),
+        }
+      );
+    }
+  }
+}
@@ -0,0 +1,185 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::types::ResultDynError;
+
+/// Everything pg_dump emits before the table data: tables, types, functions...
+pub const SCHEMA_FILE: &str = "schema.sql";
+/// One `<schema>.<table>.sql` file per table holding its `COPY` block.
+pub const DATA_DIR: &str = "data";
+/// Sequence values, indexes, constraints and triggers, applied after the data
+/// so loading tables in file order can't violate foreign keys.
+pub const POST_DATA_FILE: &str = "post-data.sql";
+
+/// Splits a plain `pg_dump` script into the schema file, per table data files
+/// and the post data file inside `dir`.
+pub fn write(script: impl BufRead, dir: &Path) -> ResultDynError<()> {
+  let data_dir = dir.join(DATA_DIR);
+
+  // Tables dropped since the previous dump must not leave their file behind
+  if data_dir.exists() {
+    fs::remove_dir_all(&data_dir)?;
+  }
+
+  fs::create_dir_all(&data_dir)?;
+
+  let mut writer = create_file(&dir.join(SCHEMA_FILE))?;
+  let mut post_data_writer = Some(create_file(&dir.join(POST_DATA_FILE))?);
+  let mut is_in_copy = false;
+  let mut has_data = false;
+
+  for line in script.split(b'\n') {
+    let line = line?;
+
+    if is_in_copy {
+      is_in_copy = line.as_slice() != b"\\.";
+    } else if line.starts_with(b"COPY ") {
+      is_in_copy = true;
+    } else if let Some(file_name) = data_file_name(&line) {
+      writer.flush()?;
+      writer = create_file(&data_dir.join(file_name))?;
+      has_data = true;
+    } else if line.starts_with(b"-- Name: ") || line.starts_with(b"-- Data for Name: ") {
+      // Sections after the table data belong to the post data file
+      if has_data && post_data_writer.is_some() {
+        writer.flush()?;
+        writer = post_data_writer.take().unwrap();
+      }
+    }
+
+    writer.write_all(&line)?;
+    writer.write_all(b"\n")?;
+  }
+
+  writer.flush()?;
+
+  if let Some(mut post_data_writer) = post_data_writer {
+    post_data_writer.flush()?;
+  }
+
+  return Ok(());
+}
+
+/// Files of the layout in `dir`, in the order they have to be restored.
+pub fn files(dir: &Path) -> ResultDynError<Vec<PathBuf>> {
+  let mut data_files: Vec<PathBuf> = match fs::read_dir(dir.join(DATA_DIR)) {
+    Ok(entries) => entries
+      .map(|entry| entry.map(|entry| entry.path()))
+      .collect::<Result<_, io::Error>>()?,
+    // git does not store empty directories, a db without tables has no data dir
+    Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+    Err(err) => return Err(err.into()),
+  };
+
+  data_files.sort();
+
+  let mut files = vec![dir.join(SCHEMA_FILE)];
+  files.append(&mut data_files);
+  files.push(dir.join(POST_DATA_FILE));
+
+  return Ok(files);
+}
+
+fn create_file(path: &Path) -> io::Result<BufWriter<File>> {
+  return Ok(BufWriter::new(File::create(path)?));
+}
+
+/// `<schema>.<table>.sql` for a header like
+/// `-- Data for Name: users; Type: TABLE DATA; Schema: public; Owner: postgres`.
+fn data_file_name(line: &[u8]) -> Option<String> {
+  let header = std::str::from_utf8(line.strip_prefix(b"-- Data for Name: ")?).ok()?;
+  let mut parts = header.split("; ");
+  let name = parts.next()?;
+  let mut object_type = "";
+  let mut schema = "";
+
+  for part in parts {
+    if let Some(value) = part.strip_prefix("Type: ") {
+      object_type = value;
+    } else if let Some(value) = part.strip_prefix("Schema: ") {
+      schema = value;
+    }
+  }
+
+  if object_type != "TABLE DATA" {
+    return None;
+  }
+
+  return Some(format!("{}.{}.sql", schema, name).replace('/', "_"));
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const SCRIPT: &str = "SET client_encoding = 'UTF8';
+--
+-- Name: users; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.users (id integer);
+
+--
+-- Data for Name: users; Type: TABLE DATA; Schema: public; Owner: postgres
+--
+
+COPY public.users (id) FROM stdin;
+1
+-- Name: not a header
+\\.
+
+--
+-- Name: users_id_seq; Type: SEQUENCE SET; Schema: public; Owner: postgres
+--
+
+SELECT pg_catalog.setval('public.users_id_seq', 1, true);
+";
+
+  struct DirCleaner {
+    dir: PathBuf,
+  }
+
+  impl Drop for DirCleaner {
+    fn drop(&mut self) {
+      fs::remove_dir_all(&self.dir).unwrap();
+    }
+  }
+
+  mod write {
+    use super::*;
+
+    #[test]
+    fn it_should_split_the_script_per_table() -> ResultDynError<()> {
+      let dir = PathBuf::from("/tmp/test-table-files");
+      let _dir_cleaner = DirCleaner { dir: dir.clone() };
+
+      fs::create_dir_all(dir.join(DATA_DIR))?;
+      fs::write(dir.join(DATA_DIR).join("public.dropped.sql"), "")?;
+      write(SCRIPT.as_bytes(), &dir)?;
+
+      let files = files(&dir)?;
+      let read = |path: &PathBuf| fs::read_to_string(path).unwrap();
+
+      assert_eq!(
+        files,
+        vec![
+          dir.join(SCHEMA_FILE),
+          dir.join(DATA_DIR).join("public.users.sql"),
+          dir.join(POST_DATA_FILE),
+        ]
+      );
+      assert!(read(&files[0]).contains("CREATE TABLE public.users"));
+      assert!(read(&files[1]).contains("-- Name: not a header"));
+      assert!(read(&files[2]).contains("pg_catalog.setval"));
+      assert_eq!(files.iter().map(read).collect::<String>(), SCRIPT);
+
+      return Ok(());
+    }
+  }
+}
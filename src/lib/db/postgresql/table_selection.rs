@@ -0,0 +1,413 @@
+use std::io;
+use std::io::Write;
+
+use failure::Fail;
+
+use crate::types::ResultDynError;
+
+#[derive(Debug, Fail)]
+pub enum TableSelectionError {
+  #[fail(display = "No table {} in the dump", name)]
+  UnknownTable { name: String },
+
+  #[fail(display = "No table in schema {} in the dump", name)]
+  UnknownSchema { name: String },
+
+  #[fail(
+    display = "Table {} is ambiguous, qualify it with its schema, candidates:\n{}",
+    name, candidates
+  )]
+  AmbiguousTable {
+    name: String,
+    /// Newline separated matching qualified table names.
+    candidates: String,
+  },
+}
+
+/// Tables of a dump picked by name or schema, with the sequences they own
+/// so their values are restored along with the rows.
+#[derive(Debug, PartialEq)]
+pub struct TableSelection {
+  /// Qualified names as written in the dump, e.g. `public."Users"`.
+  tables: Vec<String>,
+  sequences: Vec<String>,
+}
+
+impl TableSelection {
+  /// Selects tables of the schema sql from `pg_restore --schema-only`,
+  /// `table_names` are either `table` or `schema.table`. Every name
+  /// has to match a table, a `table` in several schemas is ambiguous.
+  pub fn select(
+    schema_sql: &str,
+    table_names: &[String],
+    schema_names: &[String],
+  ) -> ResultDynError<TableSelection> {
+    let mut all_tables = vec![];
+    let mut owned_sequences = vec![];
+    let mut altered_table = "";
+
+    for line in schema_sql.lines() {
+      let create_table = line
+        .strip_prefix("CREATE TABLE ")
+        .or_else(|| line.strip_prefix("CREATE UNLOGGED TABLE "));
+
+      if let Some(create_table) = create_table {
+        all_tables.push(String::from(first_word(create_table)));
+      } else if let Some(alter_sequence) = line.strip_prefix("ALTER SEQUENCE ") {
+        // ALTER SEQUENCE public.users_id_seq OWNED BY public.users.id;
+        if let Some((sequence, column)) = alter_sequence.split_once(" OWNED BY ") {
+          if let Some((table, _column)) = column.trim_end_matches(';').rsplit_once('.') {
+            owned_sequences.push((String::from(table), String::from(sequence)));
+          }
+        }
+      } else if let Some(alter_table) = line.strip_prefix("ALTER TABLE ") {
+        altered_table = first_word(alter_table.trim_start_matches("ONLY "));
+      } else if let Some(sequence) = line.trim_start().strip_prefix("SEQUENCE NAME ") {
+        // Identity columns name their sequence in the ALTER TABLE statement
+        owned_sequences.push((
+          String::from(altered_table),
+          String::from(first_word(sequence)),
+        ));
+      }
+    }
+
+    let mut tables = vec![];
+
+    for name in table_names {
+      let qualified_matches: Vec<&String> = all_tables
+        .iter()
+        .filter(|table| unquote(table) == unquote(name))
+        .collect();
+      let matches: Vec<&String> = match qualified_matches.is_empty() {
+        true => all_tables
+          .iter()
+          .filter(|table| split_qualified_name(table).1 == unquote(name))
+          .collect(),
+        false => qualified_matches,
+      };
+
+      match matches.len() {
+        0 => return Err(TableSelectionError::UnknownTable { name: name.clone() }.into()),
+        1 => tables.push(matches[0].clone()),
+        _ => {
+          return Err(
+            TableSelectionError::AmbiguousTable {
+              name: name.clone(),
+              candidates: matches
+                .iter()
+                .map(|table| table.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n"),
+            }
+            .into(),
+          )
+        }
+      }
+    }
+
+    for name in schema_names {
+      let matches: Vec<&String> = all_tables
+        .iter()
+        .filter(|table| split_qualified_name(table).0 == unquote(name))
+        .collect();
+
+      if matches.is_empty() {
+        return Err(TableSelectionError::UnknownSchema { name: name.clone() }.into());
+      }
+
+      tables.extend(matches.into_iter().cloned());
+    }
+
+    tables.sort();
+    tables.dedup();
+
+    let sequences = owned_sequences
+      .into_iter()
+      .filter(|(table, _sequence)| tables.contains(table))
+      .map(|(_table, sequence)| sequence)
+      .collect();
+
+    return Ok(TableSelection { tables, sequences });
+  }
+
+  pub fn tables(&self) -> &[String] {
+    return &self.tables;
+  }
+
+  /// Drops the foreign keys from and to the tables then empties them, rows
+  /// can then be loaded in any order even when the tables reference each other.
+  /// Definitions are read with an empty search path so they are schema qualified.
+  /// User triggers are disabled like `pg_restore --disable-triggers` does, the
+  /// rows were already processed by them when they were dumped.
+  pub fn before_data_sql(&self) -> String {
+    let tables = self
+      .tables
+      .iter()
+      .map(|table| format!("'{}'", table.replace('\'', "''")))
+      .collect::<Vec<String>>()
+      .join(", ");
+
+    return format!(
+      "SET search_path = '';
+CREATE TEMPORARY TABLE jab_foreign_keys ON COMMIT DROP AS
+SELECT conrelid::regclass AS table_name, conname AS name, pg_get_constraintdef(oid) AS definition
+FROM pg_constraint
+WHERE contype = 'f' AND (conrelid = ANY(ARRAY[{tables}]::regclass[]) OR confrelid = ANY(ARRAY[{tables}]::regclass[]));
+DO $$
+DECLARE foreign_key record;
+BEGIN
+  FOR foreign_key IN SELECT * FROM jab_foreign_keys LOOP
+    EXECUTE format('ALTER TABLE %s DROP CONSTRAINT %I', foreign_key.table_name, foreign_key.name);
+  END LOOP;
+END $$;
+{disable_triggers}TRUNCATE TABLE {truncate_tables};
+",
+      tables = tables,
+      disable_triggers = self.alter_triggers_sql("DISABLE"),
+      truncate_tables = self.tables.join(", ")
+    );
+  }
+
+  /// Enables the user triggers again and adds the foreign keys back,
+  /// which checks the loaded rows against the tables outside the selection too.
+  pub fn after_data_sql(&self) -> String {
+    return format!(
+      "{}DO $$
+DECLARE foreign_key record;
+BEGIN
+  FOR foreign_key IN SELECT * FROM jab_foreign_keys LOOP
+    EXECUTE format('ALTER TABLE %s ADD CONSTRAINT %I %s', foreign_key.table_name, foreign_key.name, foreign_key.definition);
+  END LOOP;
+END $$;
+",
+      self.alter_triggers_sql("ENABLE")
+    );
+  }
+
+  /// `action` is `DISABLE` or `ENABLE`.
+  fn alter_triggers_sql(&self, action: &str) -> String {
+    return self
+      .tables
+      .iter()
+      .map(|table| format!("ALTER TABLE {} {} TRIGGER USER;\n", table, action))
+      .collect();
+  }
+
+  fn has_table(&self, table: &str) -> bool {
+    return self.tables.iter().any(|selected| selected == table);
+  }
+
+  fn has_sequence(&self, sequence: &str) -> bool {
+    return self.sequences.iter().any(|selected| selected == sequence);
+  }
+}
+
+/// Passes through the `COPY` blocks and sequence values of the selected
+/// tables from a data only sql script, along with its session settings.
+pub struct SelectedDataWriter<'a> {
+  selection: &'a TableSelection,
+  writer: &'a mut dyn Write,
+  line: Vec<u8>,
+  is_in_copy: bool,
+  is_in_selected_copy: bool,
+}
+
+impl<'a> SelectedDataWriter<'a> {
+  pub fn new(selection: &'a TableSelection, writer: &'a mut dyn Write) -> SelectedDataWriter<'a> {
+    return SelectedDataWriter {
+      selection,
+      writer,
+      line: vec![],
+      is_in_copy: false,
+      is_in_selected_copy: false,
+    };
+  }
+
+  fn write_line(&mut self) -> io::Result<()> {
+    let line = String::from_utf8_lossy(&self.line);
+    let is_selected = if self.is_in_copy {
+      let is_selected = self.is_in_selected_copy;
+
+      if line.trim_end_matches('\r') == "\\." {
+        self.is_in_copy = false;
+        self.is_in_selected_copy = false;
+      }
+
+      is_selected
+    } else if let Some(copy) = line.strip_prefix("COPY ") {
+      self.is_in_copy = true;
+      self.is_in_selected_copy = self.selection.has_table(first_word(copy));
+
+      self.is_in_selected_copy
+    } else if let Some(setval) = line.strip_prefix("SELECT pg_catalog.setval('") {
+      let sequence = setval.split('\'').next().unwrap_or("");
+
+      self.selection.has_sequence(sequence)
+    } else {
+      line.starts_with("SET ") || line.starts_with("SELECT pg_catalog.set_config(")
+    };
+
+    if is_selected {
+      self.writer.write_all(&self.line)?;
+      self.writer.write_all(b"\n")?;
+    }
+
+    self.line.clear();
+
+    return Ok(());
+  }
+}
+
+impl<'a> Write for SelectedDataWriter<'a> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    for chunk in buf.split_inclusive(|byte| *byte == b'\n') {
+      match chunk.strip_suffix(b"\n") {
+        Some(line) => {
+          self.line.extend_from_slice(line);
+          self.write_line()?;
+        }
+        None => self.line.extend_from_slice(chunk),
+      }
+    }
+
+    return Ok(buf.len());
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    if !self.line.is_empty() {
+      self.write_line()?;
+    }
+
+    return self.writer.flush();
+  }
+}
+
+fn first_word(text: &str) -> &str {
+  return text.split_whitespace().next().unwrap_or("");
+}
+
+fn unquote(name: &str) -> String {
+  return name.replace('"', "");
+}
+
+/// `(schema, table)` without quotes.
+fn split_qualified_name(name: &str) -> (String, String) {
+  let name = unquote(name);
+
+  return match name.split_once('.') {
+    Some((schema, table)) => (String::from(schema), String::from(table)),
+    None => (String::new(), name),
+  };
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const SCHEMA_SQL: &str = "CREATE TABLE public.users (
+    id integer NOT NULL
+);
+CREATE TABLE public.\"Orders\" (
+    id integer NOT NULL
+);
+CREATE TABLE audit.logs (
+    id integer NOT NULL
+);
+ALTER SEQUENCE public.users_id_seq OWNED BY public.users.id;
+ALTER TABLE public.\"Orders\" ALTER COLUMN id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME public.\"Orders_id_seq\"
+    START WITH 1
+);
+";
+
+  fn names(names: &[&str]) -> Vec<String> {
+    return names.iter().map(|name| String::from(*name)).collect();
+  }
+
+  mod select {
+    use super::*;
+
+    #[test]
+    fn it_should_select_tables_by_name_and_schema() -> ResultDynError<()> {
+      let selection = TableSelection::select(
+        SCHEMA_SQL,
+        &names(&["public.users", "Orders"]),
+        &names(&["audit"]),
+      )?;
+
+      assert_eq!(
+        selection,
+        TableSelection {
+          tables: names(&["audit.logs", "public.\"Orders\"", "public.users"]),
+          sequences: names(&["public.users_id_seq", "public.\"Orders_id_seq\""]),
+        }
+      );
+      assert!(TableSelection::select(SCHEMA_SQL, &names(&["missing"]), &[]).is_err());
+      assert!(TableSelection::select(SCHEMA_SQL, &[], &names(&["missing"])).is_err());
+
+      return Ok(());
+    }
+
+    #[test]
+    fn it_should_reject_table_names_found_in_several_schemas() -> ResultDynError<()> {
+      let schema_sql = format!("{}CREATE TABLE audit.users (\n);\n", SCHEMA_SQL);
+      let err = TableSelection::select(&schema_sql, &names(&["users"]), &[]).unwrap_err();
+
+      assert_eq!(
+        err.to_string(),
+        "Table users is ambiguous, qualify it with its schema, candidates:\npublic.users\naudit.users"
+      );
+      assert_eq!(
+        TableSelection::select(&schema_sql, &names(&["audit.users"]), &[])?.tables(),
+        names(&["audit.users"])
+      );
+
+      return Ok(());
+    }
+  }
+
+  mod selected_data_writer {
+    use super::*;
+
+    #[test]
+    fn it_should_only_write_the_selected_tables() -> ResultDynError<()> {
+      let selection = TableSelection::select(SCHEMA_SQL, &names(&["users"]), &[])?;
+      let mut output = vec![];
+
+      assert!(selection
+        .before_data_sql()
+        .contains("ALTER TABLE public.users DISABLE TRIGGER USER;\nTRUNCATE TABLE public.users;"));
+      assert!(selection
+        .after_data_sql()
+        .starts_with("ALTER TABLE public.users ENABLE TRIGGER USER;\n"));
+
+      let mut writer = SelectedDataWriter::new(&selection, &mut output);
+
+      write!(
+        writer,
+        "SET client_encoding = 'UTF8';
+COPY public.\"Orders\" (id) FROM stdin;
+1
+\\.
+COPY public.users (id) FROM stdin;
+1
+\\.
+SELECT pg_catalog.setval('public.\"Orders_id_seq\"', 1, true);
+SELECT pg_catalog.setval('public.users_id_seq', 1, true);"
+      )?;
+      writer.flush()?;
+
+      assert_eq!(
+        String::from_utf8(output)?,
+        "SET client_encoding = 'UTF8';
+COPY public.users (id) FROM stdin;
+1
+\\.
+SELECT pg_catalog.setval('public.users_id_seq', 1, true);
+"
+      );
+
+      return Ok(());
+    }
+  }
+}
@@ -0,0 +1,119 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::thread;
+
+use crate::types::ResultDynError;
+
+/// What a failed db tool reported, its stdout can hold dump contents.
+pub(crate) fn stderr_message(output: &Output) -> String {
+  return String::from(String::from_utf8_lossy(&output.stderr).trim());
+}
+
+/// Runs the command with its stdout copied into `stdout` as it is produced.
+/// When writing into `stdout` fails the command is stopped and the write error returned.
+pub(crate) fn run_with_stdout(mut command: Command, stdout: &mut dyn Write) -> ResultDynError<()> {
+  let mut child = command
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+
+  let stderr_reader = read_in_background(child.stderr.take().unwrap());
+  let copied = io::copy(child.stdout.as_mut().unwrap(), stdout);
+
+  if let Err(err) = copied {
+    // e.g. the pager quit, closing the pipe makes the command stop writing
+    // instead of blocking on it forever
+    drop(child.stdout.take());
+    child.wait()?;
+    stderr_reader.join().unwrap()?;
+
+    return Err(err.into());
+  }
+
+  let status = child.wait()?;
+  let stderr = stderr_reader.join().unwrap()?;
+
+  if !status.success() {
+    return Err(io::Error::other(String::from_utf8_lossy(&stderr).into_owned()).into());
+  }
+
+  return Ok(());
+}
+
+/// Runs the command with `write_stdin` feeding its stdin, returns the output
+/// together with the result of `write_stdin`. A failing command usually makes
+/// `write_stdin` fail with a broken pipe so callers should check the output first.
+pub(crate) fn run_with_stdin(
+  mut command: Command,
+  write_stdin: &mut dyn FnMut(&mut dyn Write) -> ResultDynError<()>,
+) -> ResultDynError<(Output, ResultDynError<()>)> {
+  let mut child = command
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+
+  // Drain the outputs while writing so the child can't block us
+  // when its output buffers are full.
+  let stdout_reader = read_in_background(child.stdout.take().unwrap());
+  let stderr_reader = read_in_background(child.stderr.take().unwrap());
+
+  let mut stdin = child.stdin.take().unwrap();
+  let written = write_stdin(&mut stdin);
+  drop(stdin);
+
+  let status = child.wait()?;
+  let output = Output {
+    status,
+    stdout: stdout_reader.join().unwrap()?,
+    stderr: stderr_reader.join().unwrap()?,
+  };
+
+  return Ok((output, written));
+}
+
+pub(crate) fn read_in_background(
+  mut reader: impl Read + Send + 'static,
+) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+  return thread::spawn(move || {
+    let mut content = vec![];
+    reader.read_to_end(&mut content)?;
+
+    return Ok(content);
+  });
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  struct FailingWriter;
+
+  impl Write for FailingWriter {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+      return Err(io::Error::new(io::ErrorKind::BrokenPipe, "pager quit"));
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      return Ok(());
+    }
+  }
+
+  mod run_with_stdout {
+    use super::*;
+
+    #[test]
+    fn it_should_stop_the_command_when_the_writer_fails() {
+      let mut command = Command::new("sh");
+      command.arg("-c").arg("yes jab");
+
+      let result = run_with_stdout(command, &mut FailingWriter);
+
+      assert_eq!(result.unwrap_err().to_string(), "pager quit");
+    }
+  }
+}
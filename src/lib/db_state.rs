@@ -0,0 +1,127 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::DateTime;
+use chrono::FixedOffset;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::types::ResultDynError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbStateAction {
+  Commit,
+  Restore,
+}
+
+impl DbStateAction {
+  pub fn as_str(self) -> &'static str {
+    return match self {
+      DbStateAction::Commit => "committed",
+      DbStateAction::Restore => "restored",
+    };
+  }
+}
+
+/// The commit a db was last restored to or committed from,
+/// the db itself has no way to tell which state it holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DbState {
+  /// Host, port and name of the db without the credentials,
+  /// states recorded before were keyed on the whole db uri.
+  #[serde(alias = "db_uri")]
+  pub db: String,
+  pub commit_hash: String,
+  pub action: DbStateAction,
+  pub time: DateTime<FixedOffset>,
+}
+
+/// Reads the states recorded in the file, one per db.
+pub fn read(path: &Path) -> ResultDynError<Vec<DbState>> {
+  let states_str = match fs::read_to_string(path) {
+    Ok(states_str) => states_str,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+    Err(err) => return Err(err.into()),
+  };
+
+  return Ok(serde_json::from_str(&states_str)?);
+}
+
+/// Records the state, replacing the previous state of the same db.
+pub fn record(path: &Path, state: DbState) -> ResultDynError<()> {
+  let mut states = read(path)?;
+
+  states.push(state);
+
+  return write(path, &states);
+}
+
+/// Writes the states, only the latest state of each db is kept.
+pub fn write(path: &Path, states: &[DbState]) -> ResultDynError<()> {
+  fs::write(path, serde_json::to_string_pretty(&latest(states))?)?;
+
+  return Ok(());
+}
+
+/// The last state of each db in the order they were recorded.
+pub fn latest(states: &[DbState]) -> Vec<DbState> {
+  let mut latest_states: Vec<DbState> = vec![];
+
+  for state in states {
+    latest_states.retain(|latest_state| latest_state.db != state.db);
+    latest_states.push(state.clone());
+  }
+
+  return latest_states;
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  mod record {
+    use super::*;
+
+    #[test]
+    fn it_should_keep_the_latest_state_per_db() -> ResultDynError<()> {
+      let path = Path::new("/tmp/test-db-states.json");
+      let state = |db: &str, commit_hash: &str| DbState {
+        db: String::from(db),
+        commit_hash: String::from(commit_hash),
+        action: DbStateAction::Restore,
+        time: DateTime::parse_from_rfc3339("2020-01-31T13:00:00+07:00").unwrap(),
+      };
+
+      if path.exists() {
+        fs::remove_file(path)?;
+      }
+
+      assert!(read(path)?.is_empty());
+
+      record(path, state("u@localhost/db", "a"))?;
+      record(path, state("u@localhost/db_old", "b"))?;
+      record(path, state("u@localhost/db", "c"))?;
+
+      assert_eq!(
+        read(path)?,
+        vec![
+          state("u@localhost/db_old", "b"),
+          state("u@localhost/db", "c")
+        ]
+      );
+
+      fs::write(
+        path,
+        r#"[{"db_uri": "u:secret@localhost/db", "commit_hash": "a", "action": "commit", "time": "2020-01-31T13:00:00+07:00"}]"#,
+      )?;
+
+      assert_eq!(read(path)?[0].db, "u:secret@localhost/db");
+
+      fs::remove_file(path)?;
+
+      return Ok(());
+    }
+  }
+}
@@ -0,0 +1,209 @@
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::config::DumpOptions;
+
+/// Provenance of a dump, stored as trailers of the commit message
+/// so the commit tree stays the dump itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpMetadata {
+  pub server_version: String,
+  pub dump_tool_version: String,
+  /// `host[:port]`, never contains credentials.
+  pub host: String,
+  pub db_name: String,
+  pub format: String,
+  pub size: u64,
+  /// Hex encoded sha256 of the dump.
+  pub checksum: String,
+  /// Hex encoded sha256 of the dump content that stays the same across dumps
+  /// of an unchanged db, `None` for commits created before it was recorded.
+  pub content_checksum: Option<String>,
+  /// Options the dump was made with, `None` for commits created before
+  /// they were recorded.
+  pub dump_options: Option<DumpOptions>,
+}
+
+const SERVER_VERSION_TRAILER: &str = "Jab-Server-Version";
+const DUMP_TOOL_VERSION_TRAILER: &str = "Jab-Dump-Tool-Version";
+const HOST_TRAILER: &str = "Jab-Host";
+const DB_NAME_TRAILER: &str = "Jab-Database";
+const FORMAT_TRAILER: &str = "Jab-Dump-Format";
+const SIZE_TRAILER: &str = "Jab-Dump-Size";
+const CHECKSUM_TRAILER: &str = "Jab-Dump-Sha256";
+const CONTENT_CHECKSUM_TRAILER: &str = "Jab-Content-Sha256";
+const DUMP_OPTIONS_TRAILER: &str = "Jab-Dump-Options";
+
+impl DumpMetadata {
+  pub fn checksum(dump: &[u8]) -> String {
+    return format!("{:x}", Sha256::digest(dump));
+  }
+
+  /// Whether a commit of this dump would store the same as the commit of the
+  /// other dump, the content, format and options all have to match. Dumps
+  /// without a content checksum or options never match.
+  pub fn is_same_dump_as(&self, other: &DumpMetadata) -> bool {
+    return self.content_checksum.is_some()
+      && self.content_checksum == other.content_checksum
+      && self.format == other.format
+      && self.dump_options.is_some()
+      && self.dump_options == other.dump_options;
+  }
+
+  /// Appends the metadata as trailers to the given commit message.
+  pub fn to_commit_message(&self, message: &str) -> String {
+    let mut trailers = vec![
+      (SERVER_VERSION_TRAILER, self.server_version.clone()),
+      (DUMP_TOOL_VERSION_TRAILER, self.dump_tool_version.clone()),
+      (HOST_TRAILER, self.host.clone()),
+      (DB_NAME_TRAILER, self.db_name.clone()),
+      (FORMAT_TRAILER, self.format.clone()),
+      (SIZE_TRAILER, self.size.to_string()),
+      (CHECKSUM_TRAILER, self.checksum.clone()),
+    ];
+
+    if let Some(content_checksum) = self.content_checksum.as_ref() {
+      trailers.push((CONTENT_CHECKSUM_TRAILER, content_checksum.clone()));
+    }
+
+    // Serialized on a single line as json
+    if let Some(dump_options) = self.dump_options.as_ref() {
+      trailers.push((
+        DUMP_OPTIONS_TRAILER,
+        serde_json::to_string(dump_options).unwrap(),
+      ));
+    }
+
+    let trailers: Vec<String> = trailers
+      .into_iter()
+      .map(|(key, value)| format!("{}: {}", key, value))
+      .collect();
+
+    return format!("{}\n\n{}\n", message.trim_end(), trailers.join("\n"));
+  }
+
+  /// Reads the metadata back from a commit message, commits created
+  /// before metadata was recorded return `None`.
+  pub fn from_commit_message(message: &str) -> Option<DumpMetadata> {
+    let trailer = |key: &str| {
+      let prefix = format!("{}: ", key);
+
+      return message
+        .lines()
+        .filter_map(|line| line.strip_prefix(&prefix))
+        .next_back()
+        .map(String::from);
+    };
+
+    return Some(DumpMetadata {
+      server_version: trailer(SERVER_VERSION_TRAILER)?,
+      dump_tool_version: trailer(DUMP_TOOL_VERSION_TRAILER)?,
+      host: trailer(HOST_TRAILER)?,
+      db_name: trailer(DB_NAME_TRAILER)?,
+      format: trailer(FORMAT_TRAILER)?,
+      size: trailer(SIZE_TRAILER)?.parse().ok()?,
+      checksum: trailer(CHECKSUM_TRAILER)?,
+      content_checksum: trailer(CONTENT_CHECKSUM_TRAILER),
+      dump_options: trailer(DUMP_OPTIONS_TRAILER)
+        .and_then(|dump_options| serde_json::from_str(&dump_options).ok()),
+    });
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn metadata() -> DumpMetadata {
+    return DumpMetadata {
+      server_version: String::from("12.1"),
+      dump_tool_version: String::from("12.1"),
+      host: String::from("localhost:5432"),
+      db_name: String::from("testdb"),
+      format: String::from("custom"),
+      size: 3,
+      checksum: DumpMetadata::checksum(b"abc"),
+      content_checksum: Some(DumpMetadata::checksum(b"abc")),
+      dump_options: Some(DumpOptions {
+        exclude_tables: vec![String::from("audit.*")],
+        no_owner: true,
+        ..Default::default()
+      }),
+    };
+  }
+
+  mod checksum {
+    use super::*;
+
+    #[test]
+    fn it_should_return_hex_sha256() {
+      assert_eq!(
+        DumpMetadata::checksum(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+      );
+    }
+  }
+
+  mod is_same_dump_as {
+    use super::*;
+
+    #[test]
+    fn it_should_compare_the_content_format_and_options() {
+      let other_options = DumpMetadata {
+        dump_options: Some(DumpOptions::default()),
+        ..metadata()
+      };
+
+      assert!(metadata().is_same_dump_as(&metadata()));
+      assert!(!metadata().is_same_dump_as(&DumpMetadata {
+        content_checksum: Some(DumpMetadata::checksum(b"abcd")),
+        ..metadata()
+      }));
+      assert!(!metadata().is_same_dump_as(&DumpMetadata {
+        format: String::from("plain"),
+        ..metadata()
+      }));
+      assert!(!metadata().is_same_dump_as(&other_options));
+
+      let unrecorded = DumpMetadata {
+        content_checksum: None,
+        dump_options: None,
+        ..metadata()
+      };
+
+      assert!(!unrecorded.is_same_dump_as(&unrecorded));
+    }
+  }
+
+  mod from_commit_message {
+    use super::*;
+
+    #[test]
+    fn it_should_read_back_the_trailers() {
+      let message = metadata().to_commit_message("seed data\n");
+
+      assert!(message.starts_with("seed data\n\nJab-Server-Version: 12.1\n"));
+      assert_eq!(
+        DumpMetadata::from_commit_message(&message),
+        Some(metadata())
+      );
+    }
+
+    #[test]
+    fn it_should_read_back_metadata_without_content_checksum_and_dump_options() {
+      let metadata = DumpMetadata {
+        content_checksum: None,
+        dump_options: None,
+        ..metadata()
+      };
+      let message = metadata.to_commit_message("seed data");
+
+      assert_eq!(DumpMetadata::from_commit_message(&message), Some(metadata));
+    }
+
+    #[test]
+    fn it_should_return_none_without_trailers() {
+      assert!(DumpMetadata::from_commit_message("seed data").is_none());
+    }
+  }
+}
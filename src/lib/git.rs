@@ -1,17 +1,43 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process::Command;
 
+use chrono::DateTime;
+use chrono::FixedOffset;
+use chrono::TimeZone;
 use failure::Fail;
 use git2;
 use git2::Repository;
 use log;
 
+use crate::db::process::run_with_stdout;
 use crate::types::ResultDynError;
 
 #[derive(Debug, Fail)]
 pub enum GitRepoError {
   #[fail(display = "Repo is empty")]
   EmptyRepoError,
+
+  #[fail(
+    display = "Branch {} is currently checked out, please checkout another branch first",
+    name
+  )]
+  BranchIsCheckedOutError { name: String },
+
+  #[fail(
+    display = "Revision {} is ambiguous, candidates:\n{}",
+    revision, candidates
+  )]
+  AmbiguousRevisionError {
+    revision: String,
+    /// Newline separated matching commit hashes.
+    candidates: String,
+  },
 }
 
 pub struct GitRepo {
@@ -21,7 +47,88 @@ pub struct GitRepo {
 pub struct Commit<'repo> {
   pub hash: String,
   pub message: String,
+  /// Formatted as `name <email>`.
+  pub author: String,
+  pub time: DateTime<FixedOffset>,
+  pub parent_hashes: Vec<String>,
   raw_commit: git2::Commit<'repo>,
+  repo: &'repo Repository,
+}
+
+impl<'repo> Commit<'repo> {
+  pub fn summary(&self) -> &str {
+    return self.message.lines().next().unwrap_or("");
+  }
+
+  /// Total size in bytes of the files stored in the commit,
+  /// for a jab project repo this is the size of the dump.
+  pub fn dump_size(&self) -> ResultDynError<u64> {
+    let odb = self.repo.odb()?;
+    let mut size: u64 = 0;
+    let mut walk_result: Result<(), git2::Error> = Ok(());
+
+    self
+      .raw_commit
+      .tree()?
+      .walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+        if entry.kind() != Some(git2::ObjectType::Blob) {
+          return git2::TreeWalkResult::Ok;
+        }
+
+        match odb.read_header(entry.id()) {
+          Ok((blob_size, _)) => size += blob_size as u64,
+          Err(err) => {
+            walk_result = Err(err);
+
+            return git2::TreeWalkResult::Abort;
+          }
+        }
+
+        return git2::TreeWalkResult::Ok;
+      })?;
+
+    walk_result?;
+
+    return Ok(size);
+  }
+}
+
+/// Criteria to select commits when iterating the log.
+#[derive(Default)]
+pub struct CommitFilter {
+  pub since: Option<DateTime<FixedOffset>>,
+  pub until: Option<DateTime<FixedOffset>>,
+  /// Only commits whose message contains this text.
+  pub message_pattern: Option<String>,
+}
+
+impl CommitFilter {
+  pub fn matches(&self, commit: &Commit) -> bool {
+    let is_after_since = match self.since {
+      Some(since) => commit.time >= since,
+      None => true,
+    };
+    let is_before_until = match self.until {
+      Some(until) => commit.time <= until,
+      None => true,
+    };
+    let is_message_matched = match self.message_pattern.as_ref() {
+      Some(pattern) => commit.message.contains(pattern.as_str()),
+      None => true,
+    };
+
+    return is_after_since && is_before_until && is_message_matched;
+  }
+}
+
+pub struct Branch {
+  pub name: String,
+  pub is_head: bool,
+}
+
+pub struct Tag {
+  pub name: String,
+  pub commit_hash: String,
 }
 
 pub struct CommitIterator<'repo> {
@@ -75,24 +182,51 @@ impl GitRepo {
 }
 
 impl GitRepo {
+  /// Finds a commit by a git revision expression, e.g. a full or short hash,
+  /// `HEAD~3`, `HEAD^`, a branch or tag name, or `@{yesterday}`.
   pub fn find_commit_by_id(&self, hash: &str) -> ResultDynError<Commit> {
-    let commit = self.repo.find_commit(git2::Oid::from_str(hash)?)?;
+    let commit = self.resolve_revision(hash)?;
+
+    let author = format_signature(&commit.author());
+    let time = commit.time();
+    let time = FixedOffset::east_opt(time.offset_minutes() * 60)
+      .unwrap()
+      .timestamp_opt(time.seconds(), 0)
+      .unwrap();
 
     return Ok(Commit {
-      hash: String::from(hash),
+      hash: format!("{}", commit.id()),
       message: String::from(commit.message().unwrap()),
+      author,
+      time,
+      parent_hashes: commit.parent_ids().map(|oid| format!("{}", oid)).collect(),
       raw_commit: commit,
+      repo: &self.repo,
     });
   }
 
-  pub fn commit_file(&self, filepath: impl AsRef<Path>, message: &str) -> ResultDynError<()> {
+  /// Commits the file onto HEAD, returns the new commit hash or `None`
+  /// when the file has no changes.
+  pub fn commit_file(
+    &self,
+    filepath: impl AsRef<Path>,
+    message: &str,
+  ) -> ResultDynError<Option<String>> {
+    return self.commit_paths(&[filepath.as_ref()], message);
+  }
+
+  /// Commits the files and directories onto HEAD, paths that no longer
+  /// exist in the working tree are removed from the commit. Returns
+  /// the new commit hash or `None` when nothing changed.
+  pub fn commit_paths(&self, paths: &[&Path], message: &str) -> ResultDynError<Option<String>> {
     let mut repo_index = self.repo.index()?;
-    let filepath = PathBuf::from(filepath.as_ref());
+    let filepath: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
 
     // Get the old tree first that we will use to simulate `git diff --cached`
     let old_tree = self.repo.find_tree(repo_index.write_tree()?)?;
 
-    repo_index.add_path(filepath.as_ref())?;
+    repo_index.add_all(filepath.iter(), git2::IndexAddOption::DEFAULT, None)?;
+    repo_index.update_all(filepath.iter(), None)?;
     repo_index.write()?;
 
     log::debug!("Getting the oid for write tree");
@@ -109,7 +243,7 @@ impl GitRepo {
     if self.repo.is_empty()? {
       log::debug!("Creating initial commit..");
 
-      self.repo.commit(
+      let oid = self.repo.commit(
         Some("HEAD"),
         &self.repo.signature()?, // Author
         &self.repo.signature()?, // Committer
@@ -117,6 +251,8 @@ impl GitRepo {
         &current_tree,
         &vec![],
       )?;
+
+      return Ok(Some(format!("{}", oid)));
     } else {
       log::debug!("Finding repo head..");
 
@@ -138,10 +274,10 @@ impl GitRepo {
 
       // Only commit if there's changes
       if diff.deltas().len() == 0 {
-        return Ok(());
+        return Ok(None);
       }
 
-      self.repo.commit(
+      let oid = self.repo.commit(
         Some("HEAD"),
         &self.repo.signature()?, // Author
         &self.repo.signature()?, // Committer
@@ -149,11 +285,80 @@ impl GitRepo {
         &current_tree,
         &[&head_commit],
       )?;
+
+      return Ok(Some(format!("{}", oid)));
     }
+  }
+
+  /// Commits the files and directories on top of HEAD and points `refname`
+  /// to the commit, HEAD, its branch and the index are left as they are.
+  /// Returns the new commit hash.
+  pub fn commit_paths_to_ref(
+    &self,
+    refname: &str,
+    paths: &[&Path],
+    message: &str,
+  ) -> ResultDynError<String> {
+    let mut repo_index = self.repo.index()?;
+    let filepath: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+
+    repo_index.add_all(filepath.iter(), git2::IndexAddOption::DEFAULT, None)?;
+    repo_index.update_all(filepath.iter(), None)?;
+
+    let tree = self.repo.find_tree(repo_index.write_tree()?)?;
+
+    // Drops the in memory changes, the next commit must diff against HEAD
+    repo_index.read(true)?;
+
+    let parents = if self.repo.is_empty()? {
+      vec![]
+    } else {
+      vec![self.repo.head()?.peel_to_commit()?]
+    };
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+
+    let oid = self.repo.commit(
+      None,
+      &self.repo.signature()?, // Author
+      &self.repo.signature()?, // Committer
+      message,
+      &tree,
+      &parents,
+    )?;
+
+    self.set_reference(refname, &format!("{}", oid))?;
+
+    return Ok(format!("{}", oid));
+  }
+
+  /// Creates or moves `refname` to the commit.
+  pub fn set_reference(&self, refname: &str, hash: &str) -> ResultDynError<()> {
+    let commit = self.resolve_revision(hash)?;
+
+    self.repo.reference(
+      refname,
+      commit.id(),
+      true,
+      &format!("jab: update {}", refname),
+    )?;
 
     return Ok(());
   }
 
+  /// Hash of the commit `refname` points to, `None` when it does not exist.
+  pub fn reference_commit_hash(&self, refname: &str) -> ResultDynError<Option<String>> {
+    return match self.repo.find_reference(refname) {
+      Ok(reference) => Ok(Some(format!("{}", reference.peel_to_commit()?.id()))),
+      Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+      Err(err) => Err(err.into()),
+    };
+  }
+
+  /// The `.git` dir, for files that belong to the repo but are not versioned.
+  pub fn git_dir(&self) -> &Path {
+    return self.repo.path();
+  }
+
   pub fn commit_iterator(&self) -> ResultDynError<CommitIterator> {
     self.make_sure_repo_not_empty()?;
 
@@ -178,22 +383,277 @@ impl GitRepo {
       .map(|commit: Commit| commit.hash);
   }
 
-  pub fn get_file_content_at_commit(
+  /// Streams the content of the file at the commit into `writer`, loose
+  /// objects are read in chunks so big dumps are never held in memory.
+  pub fn write_file_at_commit(
     &self,
     filepath: impl AsRef<Path>,
     hash: &str,
-  ) -> ResultDynError<Vec<u8>> {
-    let filepath = PathBuf::from(filepath.as_ref());
+    writer: &mut dyn Write,
+  ) -> ResultDynError<()> {
+    let commit = self.find_commit_by_id(hash)?;
+    let entry = commit.raw_commit.tree()?.get_path(filepath.as_ref())?;
+
+    return self.write_blob(entry.id(), writer);
+  }
+
+  fn write_blob(&self, oid: git2::Oid, writer: &mut dyn Write) -> ResultDynError<()> {
+    let odb = self.repo.odb()?;
+
+    match odb.reader(oid) {
+      // The loose object stream does not signal the end of the object,
+      // it has to be bounded by the object size.
+      Ok((reader, size, _)) => {
+        io::copy(&mut reader.take(size as u64), writer)?;
+      }
+      // Packed objects can't be streamed by libgit2, git pipes them instead
+      Err(_) => {
+        let mut command = Command::new("git");
+
+        command
+          .arg(format!("--git-dir={}", self.repo.path().display()))
+          .args(["cat-file", "blob", &oid.to_string()]);
+
+        match run_with_stdout(command, writer) {
+          Err(err) if is_command_not_found(&err) => {
+            log::warn!("git is not installed, loading blob {} in memory", oid);
+
+            writer.write_all(self.repo.find_blob(oid)?.content())?;
+          }
+          result => result?,
+        }
+      }
+    }
+
+    return Ok(());
+  }
+
+  /// Paths of the files under the directory at the commit, in tree order.
+  pub fn file_paths_at_commit(
+    &self,
+    dirpath: impl AsRef<Path>,
+    hash: &str,
+  ) -> ResultDynError<Vec<PathBuf>> {
+    let commit = self.find_commit_by_id(hash)?;
+    let dirpath = dirpath.as_ref();
+    let entry = commit.raw_commit.tree()?.get_path(dirpath)?;
+    let mut file_paths = vec![];
+
+    self
+      .repo
+      .find_tree(entry.id())?
+      .walk(git2::TreeWalkMode::PreOrder, |parent_path, entry| {
+        if entry.kind() == Some(git2::ObjectType::Blob) {
+          file_paths.push(dirpath.join(parent_path).join(entry.name().unwrap_or("")));
+        }
+
+        return git2::TreeWalkResult::Ok;
+      })?;
+
+    return Ok(file_paths);
+  }
+
+  pub fn path_exists_at_commit(&self, path: impl AsRef<Path>, hash: &str) -> ResultDynError<bool> {
+    let commit = self.find_commit_by_id(hash)?;
+
+    return match commit.raw_commit.tree()?.get_path(path.as_ref()) {
+      Ok(_) => Ok(true),
+      Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(false),
+      Err(err) => Err(err.into()),
+    };
+  }
+
+  /// Writes the file or directory stored at `path` in the commit to `target`.
+  pub fn export_path_at_commit(
+    &self,
+    path: impl AsRef<Path>,
+    hash: &str,
+    target: impl AsRef<Path>,
+  ) -> ResultDynError<()> {
     let commit = self.find_commit_by_id(hash)?;
-    let commit_tree = commit.raw_commit.tree()?;
-    let sql = commit_tree.get_path(filepath.as_ref())?;
-    let sql = sql.to_object(&self.repo)?;
-    let sql = sql
-      .as_blob()
-      .expect(&format!("{:?} is not a blob", filepath))
-      .content();
+    let entry = commit.raw_commit.tree()?.get_path(path.as_ref())?;
+    let target = target.as_ref();
+
+    if entry.kind() == Some(git2::ObjectType::Blob) {
+      return self.write_blob(entry.id(), &mut File::create(target)?);
+    }
+
+    let tree = self.repo.find_tree(entry.id())?;
+    let mut walk_result: ResultDynError<()> = Ok(());
+
+    fs::create_dir_all(target)?;
+
+    tree.walk(git2::TreeWalkMode::PreOrder, |parent_path, entry| {
+      let entry_path = target.join(parent_path).join(entry.name().unwrap_or(""));
+      let written = match entry.kind() {
+        Some(git2::ObjectType::Tree) => fs::create_dir_all(&entry_path).map_err(Into::into),
+        Some(git2::ObjectType::Blob) => File::create(&entry_path)
+          .map_err(Into::into)
+          .and_then(|mut file| self.write_blob(entry.id(), &mut file)),
+        _ => Ok(()),
+      };
+
+      if let Err(err) = written {
+        walk_result = Err(err);
+
+        return git2::TreeWalkResult::Abort;
+      }
+
+      return git2::TreeWalkResult::Ok;
+    })?;
+
+    return walk_result;
+  }
+
+  pub fn create_branch(&self, name: &str) -> ResultDynError<()> {
+    self.make_sure_repo_not_empty()?;
+
+    let head_commit = self.repo.head()?.peel_to_commit()?;
+
+    self.repo.branch(name, &head_commit, false)?;
+
+    return Ok(());
+  }
+
+  pub fn branches(&self) -> ResultDynError<Vec<Branch>> {
+    let mut branches = vec![];
+
+    for branch in self.repo.branches(Some(git2::BranchType::Local))? {
+      let (branch, _branch_type) = branch?;
+
+      branches.push(Branch {
+        name: String::from(branch.name()?.unwrap_or("")),
+        is_head: branch.is_head(),
+      });
+    }
+
+    return Ok(branches);
+  }
+
+  pub fn delete_branch(&self, name: &str) -> ResultDynError<()> {
+    let mut branch = self.repo.find_branch(name, git2::BranchType::Local)?;
+
+    if branch.is_head() {
+      return Err(
+        GitRepoError::BranchIsCheckedOutError {
+          name: String::from(name),
+        }
+        .into(),
+      );
+    }
+
+    branch.delete()?;
+
+    return Ok(());
+  }
+
+  /// Points HEAD to the given branch and syncs the index and working tree
+  /// with it so the next `commit_file` diffs against the branch tree.
+  pub fn checkout_branch(&self, name: &str) -> ResultDynError<()> {
+    let branch = self.repo.find_branch(name, git2::BranchType::Local)?;
+    let refname = branch.get().name().unwrap();
+
+    log::debug!("Setting HEAD to {}", refname);
+
+    self.repo.set_head(refname)?;
+    self
+      .repo
+      .checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+
+    return Ok(());
+  }
+
+  /// Tags the commit the revision resolves to, returns its hash.
+  pub fn create_tag(&self, name: &str, hash: &str) -> ResultDynError<String> {
+    let commit = self.find_commit_by_id(hash)?;
+
+    self
+      .repo
+      .tag_lightweight(name, commit.raw_commit.as_object(), false)?;
+
+    return Ok(commit.hash);
+  }
+
+  pub fn tags(&self) -> ResultDynError<Vec<Tag>> {
+    let mut tags = vec![];
+
+    for name in self.repo.tag_names(None)?.iter().flatten() {
+      let commit = self.resolve_revision(&format!("refs/tags/{}", name))?;
+
+      tags.push(Tag {
+        name: String::from(name),
+        commit_hash: format!("{}", commit.id()),
+      });
+    }
+
+    return Ok(tags);
+  }
+
+  pub fn delete_tag(&self, name: &str) -> ResultDynError<()> {
+    self.repo.tag_delete(name)?;
+
+    return Ok(());
+  }
+
+  fn resolve_revision(&self, revision: &str) -> ResultDynError<git2::Commit<'_>> {
+    let object = match self.repo.revparse_single(revision) {
+      Ok(object) => object,
+      Err(err) if err.code() == git2::ErrorCode::Ambiguous => {
+        // libgit2 also treats prefixes shorter than 4 chars as ambiguous
+        let candidates = self.commit_hashes_with_prefix(revision)?;
+
+        match candidates.len() {
+          0 => return Err(err.into()),
+          1 => {
+            let oid = git2::Oid::from_str(&candidates[0])?;
+
+            return Ok(self.repo.find_commit(oid)?);
+          }
+          _ => {}
+        }
+
+        return Err(
+          GitRepoError::AmbiguousRevisionError {
+            revision: String::from(revision),
+            candidates: candidates.join("\n"),
+          }
+          .into(),
+        );
+      }
+      Err(err) => return Err(err.into()),
+    };
+
+    return Ok(object.peel_to_commit()?);
+  }
+
+  fn commit_hashes_with_prefix(&self, prefix: &str) -> ResultDynError<Vec<String>> {
+    let odb = self.repo.odb()?;
+    let prefix = prefix.to_lowercase();
+    let mut oids = vec![];
+
+    odb.foreach(|oid| {
+      if format!("{}", oid).starts_with(&prefix) {
+        oids.push(*oid);
+      }
+
+      return true;
+    })?;
+
+    let mut hashes = vec![];
+
+    for oid in oids {
+      if odb.read_header(oid)?.1 == git2::ObjectType::Commit {
+        hashes.push(format!("{}", oid));
+      }
+    }
+
+    hashes.sort();
 
-    return Ok(Vec::from(sql));
+    return Ok(hashes);
+  }
+
+  pub fn is_empty(&self) -> ResultDynError<bool> {
+    return Ok(self.repo.is_empty()?);
   }
 
   fn make_sure_repo_not_empty(&self) -> ResultDynError<()> {
@@ -205,10 +665,26 @@ impl GitRepo {
   }
 }
 
+fn format_signature(signature: &git2::Signature) -> String {
+  return format!(
+    "{} <{}>",
+    signature.name().unwrap_or(""),
+    signature.email().unwrap_or("")
+  );
+}
+
+/// Whether running a command failed because its program can't be found,
+/// nothing was written by the command then.
+fn is_command_not_found(err: &failure::Error) -> bool {
+  return match err.downcast_ref::<io::Error>() {
+    Some(err) => err.kind() == io::ErrorKind::NotFound,
+    None => false,
+  };
+}
+
 #[cfg(test)]
 mod test {
   use super::*;
-  use std::fs;
 
   struct DirCleaner {
     dir: String,
@@ -220,6 +696,26 @@ mod test {
     }
   }
 
+  fn repo_with_commit(repo_path: &str) -> ResultDynError<GitRepo> {
+    let repo = GitRepo::upsert(repo_path)?;
+    let mut config = repo.repo.config()?;
+
+    config.set_str("user.name", "jab")?;
+    config.set_str("user.email", "jab@localhost")?;
+    fs::write(PathBuf::from(repo_path).join("dump.sql"), "initial")?;
+    repo.commit_file("dump.sql", "initial")?;
+
+    return Ok(repo);
+  }
+
+  /// The branch HEAD points to, its initial name depends on the git config.
+  fn head_branch_name(repo: &GitRepo) -> ResultDynError<String> {
+    let branches = repo.branches()?;
+    let head_branch = branches.into_iter().find(|branch| branch.is_head).unwrap();
+
+    return Ok(head_branch.name);
+  }
+
   mod upsert {
     use super::*;
 
@@ -243,4 +739,298 @@ mod test {
     #[test]
     fn it_should_create_initial_commit_on_bare_repo() {}
   }
+
+  mod branch {
+    use super::*;
+
+    #[test]
+    fn it_should_create_checkout_and_delete_branches() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-branch");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let initial_branch_name = head_branch_name(&repo)?;
+
+      repo.create_branch("feature")?;
+      repo.checkout_branch("feature")?;
+
+      let branches = repo.branches()?;
+      let feature = branches.iter().find(|branch| branch.is_head).unwrap();
+
+      assert_eq!(branches.len(), 2);
+      assert_eq!(feature.name, "feature");
+      assert!(repo.delete_branch("feature").is_err());
+
+      repo.checkout_branch(&initial_branch_name)?;
+      repo.delete_branch("feature")?;
+
+      assert_eq!(repo.branches()?.len(), 1);
+
+      return Ok(());
+    }
+
+    #[test]
+    fn it_should_commit_to_the_checked_out_branch_only() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-branch-commit");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let initial_commit_hash = repo.last_commit_hash()?;
+      let initial_branch_name = head_branch_name(&repo)?;
+
+      repo.create_branch("feature")?;
+      repo.checkout_branch("feature")?;
+      fs::write(PathBuf::from(&repo_path).join("dump.sql"), "feature")?;
+      repo.commit_file("dump.sql", "feature")?;
+
+      assert_eq!(repo.commit_iterator()?.count(), 2);
+
+      repo.checkout_branch(&initial_branch_name)?;
+
+      assert_eq!(repo.last_commit_hash()?, initial_commit_hash);
+      assert_eq!(
+        fs::read_to_string(PathBuf::from(&repo_path).join("dump.sql"))?,
+        "initial"
+      );
+
+      return Ok(());
+    }
+  }
+
+  mod commit_paths {
+    use super::*;
+
+    #[test]
+    fn it_should_commit_to_a_ref_without_moving_head() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-commit-paths-to-ref");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let initial_commit_hash = repo.last_commit_hash()?;
+
+      assert!(repo.reference_commit_hash("refs/jab/test")?.is_none());
+
+      fs::write(PathBuf::from(&repo_path).join("dump.sql"), "snapshot")?;
+      let snapshot_hash =
+        repo.commit_paths_to_ref("refs/jab/test", &[Path::new("dump.sql")], "snapshot")?;
+      let mut content = vec![];
+      repo.write_file_at_commit("dump.sql", "refs/jab/test", &mut content)?;
+
+      assert_eq!(repo.last_commit_hash()?, initial_commit_hash);
+      assert_eq!(
+        repo.reference_commit_hash("refs/jab/test")?,
+        Some(snapshot_hash.clone())
+      );
+      assert_eq!(content, b"snapshot");
+      assert_eq!(
+        repo.find_commit_by_id(&snapshot_hash)?.parent_hashes,
+        vec![initial_commit_hash]
+      );
+
+      // The index still matches HEAD, the snapshot content is a change to commit
+      assert!(repo.commit_file("dump.sql", "after snapshot")?.is_some());
+
+      return Ok(());
+    }
+
+    #[test]
+    fn it_should_commit_and_export_directories() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-commit-paths");
+      let export_path = String::from("/tmp/test-repo-commit-paths-export");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let _export_dir_cleaner = DirCleaner {
+        dir: export_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let dump_dir = PathBuf::from(&repo_path).join("dump");
+
+      fs::create_dir(&dump_dir)?;
+      fs::write(dump_dir.join("toc.dat"), "toc")?;
+      fs::remove_file(PathBuf::from(&repo_path).join("dump.sql"))?;
+      repo.commit_paths(&[Path::new("dump.sql"), Path::new("dump")], "directory")?;
+      repo.export_path_at_commit("dump", "HEAD", &export_path)?;
+
+      assert_eq!(
+        fs::read_to_string(PathBuf::from(&export_path).join("toc.dat"))?,
+        "toc"
+      );
+      assert!(!repo.path_exists_at_commit("dump.sql", "HEAD")?);
+      assert!(repo.path_exists_at_commit("dump.sql", "HEAD~1")?);
+
+      let mut content = vec![];
+      repo.write_file_at_commit("dump/toc.dat", "HEAD", &mut content)?;
+
+      assert_eq!(content, b"toc");
+      assert_eq!(
+        repo.file_paths_at_commit("dump", "HEAD")?,
+        vec![PathBuf::from("dump/toc.dat")]
+      );
+
+      return Ok(());
+    }
+  }
+
+  mod write_file_at_commit {
+    use super::*;
+
+    #[test]
+    fn it_should_write_packed_files() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-write-packed-file");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let output = Command::new("git")
+        .arg("-C")
+        .arg(&repo_path)
+        .args(["gc", "--quiet", "--prune=now"])
+        .output()?;
+
+      assert!(output.status.success());
+
+      let mut content = vec![];
+      repo.write_file_at_commit("dump.sql", "HEAD", &mut content)?;
+
+      assert_eq!(content, b"initial");
+
+      return Ok(());
+    }
+  }
+
+  mod tag {
+    use super::*;
+
+    #[test]
+    fn it_should_find_commits_by_tag_name() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-tag");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let commit_hash = repo.last_commit_hash()?;
+
+      assert_eq!(repo.create_tag("clean-seed", "HEAD")?, commit_hash);
+      assert_eq!(repo.find_commit_by_id("clean-seed")?.hash, commit_hash);
+      assert_eq!(repo.tags()?[0].name, "clean-seed");
+      assert_eq!(repo.tags()?[0].commit_hash, commit_hash);
+
+      repo.delete_tag("clean-seed")?;
+
+      assert!(repo.tags()?.is_empty());
+      assert!(repo.find_commit_by_id("clean-seed").is_err());
+
+      return Ok(());
+    }
+  }
+
+  mod find_commit_by_id {
+    use super::*;
+
+    #[test]
+    fn it_should_resolve_revision_expressions() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-revision");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let initial_commit_hash = repo.last_commit_hash()?;
+
+      fs::write(PathBuf::from(&repo_path).join("dump.sql"), "second")?;
+      repo.commit_file("dump.sql", "second")?;
+
+      let second_commit_hash = repo.last_commit_hash()?;
+
+      assert_eq!(repo.find_commit_by_id("HEAD")?.hash, second_commit_hash);
+      assert_eq!(repo.find_commit_by_id("HEAD~1")?.hash, initial_commit_hash);
+      assert_eq!(repo.find_commit_by_id("HEAD^")?.hash, initial_commit_hash);
+      assert_eq!(
+        repo.find_commit_by_id(&head_branch_name(&repo)?)?.hash,
+        second_commit_hash
+      );
+      assert_eq!(
+        repo.find_commit_by_id(&initial_commit_hash[..7])?.hash,
+        initial_commit_hash
+      );
+
+      return Ok(());
+    }
+
+    #[test]
+    fn it_should_list_commits_matching_a_prefix() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-revision-prefix");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let commit_hash = repo.last_commit_hash()?;
+
+      assert_eq!(
+        repo.commit_hashes_with_prefix(&commit_hash[..1])?,
+        vec![commit_hash]
+      );
+
+      return Ok(());
+    }
+  }
+
+  mod commit_metadata {
+    use super::*;
+
+    #[test]
+    fn it_should_expose_author_parents_and_dump_size() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-commit-metadata");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let initial_commit_hash = repo.last_commit_hash()?;
+
+      fs::write(PathBuf::from(&repo_path).join("dump.sql"), "second dump")?;
+      repo.commit_file("dump.sql", "second\n\nmore details")?;
+
+      let commit = repo.find_commit_by_id("HEAD")?;
+
+      assert_eq!(commit.author, "jab <jab@localhost>");
+      assert_eq!(commit.parent_hashes, vec![initial_commit_hash]);
+      assert_eq!(commit.summary(), "second");
+      assert_eq!(commit.dump_size()?, "second dump".len() as u64);
+
+      return Ok(());
+    }
+
+    #[test]
+    fn it_should_filter_commits_by_time_and_message() -> ResultDynError<()> {
+      let repo_path = String::from("/tmp/test-repo-commit-filter");
+      let _dir_cleaner = DirCleaner {
+        dir: repo_path.clone(),
+      };
+      let repo = repo_with_commit(&repo_path)?;
+      let commit = repo.find_commit_by_id("HEAD")?;
+
+      assert!(CommitFilter::default().matches(&commit));
+      assert!(CommitFilter {
+        message_pattern: Some(String::from("init")),
+        since: Some(commit.time),
+        ..CommitFilter::default()
+      }
+      .matches(&commit));
+      assert!(!CommitFilter {
+        message_pattern: Some(String::from("seed")),
+        ..CommitFilter::default()
+      }
+      .matches(&commit));
+      assert!(!CommitFilter {
+        until: Some(commit.time - chrono::Duration::seconds(1)),
+        ..CommitFilter::default()
+      }
+      .matches(&commit));
+
+      return Ok(());
+    }
+  }
 }
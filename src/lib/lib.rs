@@ -1,5 +1,7 @@
 pub mod config;
 pub mod db;
+pub mod db_state;
+pub mod dump_metadata;
 pub mod git;
 pub mod project;
 pub mod project_manager;
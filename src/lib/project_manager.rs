@@ -1,5 +1,8 @@
 use std::path::Path;
 
+use crate::config::DbEngine;
+use crate::config::DumpFormat;
+use crate::config::DumpOptions;
 use crate::config::JabConfig;
 use crate::project::Project;
 use crate::types::ResultDynError;
@@ -7,13 +10,25 @@ use crate::types::ResultDynError;
 pub struct CreateProjectInput<'a> {
   pub project_dir: &'a Path,
   pub project_name: &'a str,
+  pub engine: DbEngine,
   pub db_uri: &'a str,
+  pub service: Option<&'a str>,
+  pub password_env: Option<&'a str>,
+  pub dump_format: DumpFormat,
+  pub terminate_connections: Option<bool>,
+  pub dump_options: &'a DumpOptions,
 }
 
 pub struct OpenProjectInput<'a> {
   pub project_dir: &'a Path,
   pub project_name: &'a str,
+  pub engine: DbEngine,
   pub db_uri: &'a str,
+  pub service: Option<&'a str>,
+  pub password_env: Option<&'a str>,
+  pub dump_format: DumpFormat,
+  pub terminate_connections: Option<bool>,
+  pub dump_options: &'a DumpOptions,
 }
 
 pub trait ProjectManager {